@@ -23,6 +23,7 @@ else
     echo "If you're using zsh or bash, please run the following command to install ask.sh:"
     echo "zsh -c \"\$(curl -fsSL https://raw.githubusercontent.com/hmirin/ask.sh/main/install.sh)\""
     echo "bash -c \"\$(curl -fsSL https://raw.githubusercontent.com/hmirin/ask.sh/main/install.sh)\""
+    echo "fish users: install with 'cargo install ask-sh' and add 'ask-sh --init fish | source' to ~/.config/fish/config.fish"
     echo "If you are using a shell other than zsh or bash, this installer does not support it. However, ask.sh may work with manual install. Follow the instructions on https://github.com/hmirin/ask.sh#installation"
     exit 1
 fi
@@ -62,13 +63,6 @@ if ! command -v cargo >/dev/null 2>&1; then
 fi
 echo "📦 Cargo is installed. Proceeding with installation."
 
-# Check if peco is installed
-if ! command -v peco >/dev/null 2>&1; then
-    echo "Peco is not installed. See https://github.com/peco/peco#installation for installation instructions"
-    exit 1
-fi
-echo "🍬 Peco is installed. Proceeding with installation."
-
 # Check if tmux is installed
 if ! command -v tmux >/dev/null 2>&1; then
     echo "Tmux is not installed. ask.sh uses tmux to capture current terminal screen and send to API."
@@ -0,0 +1,334 @@
+//! Token estimates of the request and fitting it in the context window of the model
+
+use std::collections::HashMap;
+
+use crate::llm::ChatMessage;
+use crate::session::Exchange;
+
+/// Context windows by model name prefix; the longest matching prefix wins
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-3.5-turbo", 16_385),
+    ("gpt-4", 8_192),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini", 1_048_576),
+    ("gemini-1.5-pro", 2_097_152),
+    ("llama3", 8_192),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("qwen2.5", 32_768),
+    ("mistral", 32_768),
+    ("deepseek", 65_536),
+];
+
+/// Used for models missing from the table
+const FALLBACK_CONTEXT_WINDOW: usize = 8_192;
+
+/// Ollama truncates prompts to this window unless `num_ctx` is set
+const OLLAMA_DEFAULT_NUM_CTX: usize = 2_048;
+
+/// Tokens added by the chat format around each message
+const MESSAGE_OVERHEAD: usize = 4;
+
+/// The context window of `model`. `overrides` is the `[context_windows]` table of the config
+/// file, keyed by model name prefix like the built-in table.
+/// Ollama always gets `num_ctx`, as it cuts longer prompts.
+pub fn context_window(
+    provider: &str,
+    model: &str,
+    num_ctx: Option<u32>,
+    overrides: &HashMap<String, usize>,
+) -> usize {
+    if provider == "ollama" {
+        return num_ctx.map_or(OLLAMA_DEFAULT_NUM_CTX, |num_ctx| num_ctx as usize);
+    }
+    // e.g. `openai/gpt-4o` on NanoGPT or `models/gemini-1.5-pro`
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let longest = |table: &mut dyn Iterator<Item = (&str, usize)>| {
+        table
+            .filter(|(prefix, _)| name.starts_with(prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, window)| window)
+    };
+    longest(
+        &mut overrides
+            .iter()
+            .map(|(prefix, window)| (prefix.as_str(), *window)),
+    )
+    .or_else(|| longest(&mut CONTEXT_WINDOWS.iter().copied()))
+    .unwrap_or(FALLBACK_CONTEXT_WINDOW)
+}
+
+/// Heuristic token counter, calibrated per tokenizer family
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Estimator {
+    /// Characters per token of ASCII text; other characters count as one token each
+    chars_per_token: f64,
+}
+
+impl Estimator {
+    pub fn for_model(provider: &str, model: &str) -> Self {
+        let name = model.rsplit('/').next().unwrap_or(model);
+        let chars_per_token = if provider == "anthropic" || name.starts_with("claude") {
+            3.5
+        } else if ["gpt-4o", "gpt-4.1", "o1", "o3", "o4"]
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+        {
+            // o200k_base packs more characters per token than cl100k_base
+            4.2
+        } else {
+            4.0
+        };
+        Self { chars_per_token }
+    }
+
+    pub fn tokens(&self, text: &str) -> usize {
+        let ascii = text.chars().filter(char::is_ascii).count();
+        let other = text.chars().count() - ascii;
+        self.count(ascii, other)
+    }
+
+    fn count(&self, ascii: usize, other: usize) -> usize {
+        (ascii as f64 / self.chars_per_token).ceil() as usize + other
+    }
+
+    pub fn messages(&self, messages: &[ChatMessage]) -> usize {
+        messages
+            .iter()
+            .map(|message| self.tokens(&message.content) + MESSAGE_OVERHEAD)
+            .sum()
+    }
+
+    /// Whole lines from the end of `text` within `tokens`, marking what was cut
+    fn keep_tail(&self, text: &str, tokens: usize) -> String {
+        let marker = "[... trimmed to fit the context window ...]";
+        let mut budget = tokens.saturating_sub(self.tokens(marker) + 1);
+        let lines: Vec<&str> = text.lines().collect();
+        let mut start = lines.len();
+        while start > 0 && self.tokens(lines[start - 1]) < budget {
+            budget -= self.tokens(lines[start - 1]) + 1;
+            start -= 1;
+        }
+        if start == lines.len() {
+            return String::new();
+        }
+        format!("{}\n{}", marker, lines[start..].join("\n"))
+    }
+
+    /// The beginning of `text` within `tokens`
+    fn keep_head(&self, text: &str, tokens: usize) -> String {
+        let (mut ascii, mut other) = (0, 0);
+        for (i, c) in text.char_indices() {
+            if c.is_ascii() {
+                ascii += 1;
+            } else {
+                other += 1;
+            }
+            if self.count(ascii, other) > tokens {
+                return text[..i].to_string();
+            }
+        }
+        text.to_string()
+    }
+}
+
+/// Template variables given up to fit the window, first to last
+const TRUNCATION_ORDER: &[&str] = &["pane_text", "history", "last_command", "user_input"];
+
+/// A part of the request that was shortened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Truncation {
+    /// `session` or the template variable
+    pub part: String,
+    pub removed_tokens: usize,
+}
+
+/// Build the messages with `build`, shortening the request until it fits in `available` tokens.
+/// Earlier session exchanges go first, then the pane, the shell history, the last command, and
+/// the user input last. The messages are returned even if they still do not fit.
+pub fn fit<F>(
+    vars: &mut HashMap<String, String>,
+    exchanges: &mut Vec<Exchange>,
+    available: usize,
+    estimator: &Estimator,
+    build: F,
+) -> (Vec<ChatMessage>, Vec<Truncation>)
+where
+    F: Fn(&HashMap<String, String>, &[Exchange]) -> Vec<ChatMessage>,
+{
+    let mut truncations: Vec<Truncation> = Vec::new();
+    let mut part = 0;
+    loop {
+        let messages = build(vars, exchanges);
+        let used = estimator.messages(&messages);
+        if used <= available {
+            return (messages, truncations);
+        }
+        let overflow = used - available;
+
+        let name = if !exchanges.is_empty() {
+            exchanges.remove(0);
+            "session"
+        } else {
+            while part < TRUNCATION_ORDER.len()
+                && vars
+                    .get(TRUNCATION_ORDER[part])
+                    .is_none_or(String::is_empty)
+            {
+                part += 1;
+            }
+            let Some(name) = TRUNCATION_ORDER.get(part) else {
+                return (messages, truncations);
+            };
+            let text = &vars[*name];
+            let keep = estimator.tokens(text).saturating_sub(overflow);
+            let shortened = if *name == "user_input" {
+                estimator.keep_head(text, keep)
+            } else {
+                estimator.keep_tail(text, keep)
+            };
+            if shortened.len() >= text.len() {
+                // no progress, e.g. a single long line: drop the part
+                vars.insert(name.to_string(), String::new());
+            } else {
+                vars.insert(name.to_string(), shortened);
+            }
+            name
+        };
+
+        let removed = used.saturating_sub(estimator.messages(&build(vars, exchanges)));
+        match truncations
+            .iter_mut()
+            .find(|truncation| truncation.part == name)
+        {
+            Some(truncation) => truncation.removed_tokens += removed,
+            None => truncations.push(Truncation {
+                part: name.to_string(),
+                removed_tokens: removed,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(vars: &HashMap<String, String>, exchanges: &[Exchange]) -> Vec<ChatMessage> {
+        let mut messages = vec![ChatMessage::system("You help with shells.")];
+        for exchange in exchanges {
+            messages.push(ChatMessage::user(exchange.user_input.clone()));
+            messages.push(ChatMessage::assistant(exchange.response.clone()));
+        }
+        messages.push(ChatMessage::user(format!(
+            "{}\n{}\n{}\n{}",
+            vars["pane_text"], vars["history"], vars["last_command"], vars["user_input"]
+        )));
+        messages
+    }
+
+    fn vars(pane_lines: usize) -> HashMap<String, String> {
+        let pane: Vec<String> = (0..pane_lines)
+            .map(|i| format!("warning: unused variable number {}", i))
+            .collect();
+        [
+            ("pane_text", pane.join("\n")),
+            ("history", "$ cargo build\n$ cargo test".to_string()),
+            ("last_command", "`cargo test` exited 101".to_string()),
+            ("user_input", "why did the tests fail?".to_string()),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), value))
+        .collect()
+    }
+
+    #[test]
+    fn test_context_windows() {
+        let none = HashMap::new();
+        assert_eq!(
+            context_window("openai", "gpt-4o-mini", None, &none),
+            128_000
+        );
+        assert_eq!(context_window("openai", "gpt-4", None, &none), 8_192);
+        assert_eq!(
+            context_window("nanogpt", "anthropic/claude-3.5-sonnet", None, &none),
+            200_000
+        );
+        assert_eq!(context_window("ollama", "llama3.2", None, &none), 2_048);
+        assert_eq!(
+            context_window("ollama", "llama3.2", Some(8192), &none),
+            8_192
+        );
+        assert_eq!(context_window("azure", "my-deployment", None, &none), 8_192);
+        let overrides = HashMap::from([("my-".to_string(), 32_000)]);
+        assert_eq!(
+            context_window("azure", "my-deployment", None, &overrides),
+            32_000
+        );
+    }
+
+    #[test]
+    fn test_estimates() {
+        let gpt = Estimator::for_model("openai", "gpt-3.5-turbo");
+        assert_eq!(gpt.tokens("abcdefgh"), 2);
+        assert_eq!(gpt.tokens("日本語"), 3);
+        let claude = Estimator::for_model("anthropic", "claude-3-5-sonnet-latest");
+        assert!(claude.tokens(&"x".repeat(700)) > gpt.tokens(&"x".repeat(700)));
+        assert_eq!(gpt.keep_head("abcdefghij", 2), "abcdefgh");
+        assert_eq!(gpt.keep_head("ab日本語", 2), "ab日");
+        assert_eq!(gpt.keep_head("abc", 5), "abc");
+    }
+
+    #[test]
+    fn test_fit_gives_up_the_pane_first() {
+        let estimator = Estimator::for_model("openai", "gpt-4");
+        let mut vars = vars(200);
+        let mut exchanges = Vec::new();
+        let (messages, truncations) = fit(&mut vars, &mut exchanges, 500, &estimator, build);
+        assert!(estimator.messages(&messages) <= 500);
+        assert_eq!(truncations.len(), 1);
+        assert_eq!(truncations[0].part, "pane_text");
+        // the most recent lines are kept
+        assert!(vars["pane_text"].ends_with("warning: unused variable number 199"));
+        assert_eq!(vars["user_input"], "why did the tests fail?");
+        assert_eq!(vars["last_command"], "`cargo test` exited 101");
+
+        // nothing to cut
+        let mut vars = super::tests::vars(3);
+        let (_, truncations) = fit(&mut vars, &mut exchanges, 500, &estimator, build);
+        assert!(truncations.is_empty());
+    }
+
+    #[test]
+    fn test_fit_keeps_the_user_input_last() {
+        let estimator = Estimator::for_model("openai", "gpt-4");
+        let mut vars = vars(50);
+        let mut exchanges = vec![Exchange::new(
+            "openai",
+            "gpt-4",
+            "hi",
+            "hi",
+            &"hello ".repeat(100),
+            &[],
+        )];
+        let (messages, truncations) = fit(&mut vars, &mut exchanges, 22, &estimator, build);
+        let parts: Vec<&str> = truncations
+            .iter()
+            .map(|truncation| truncation.part.as_str())
+            .collect();
+        assert_eq!(
+            parts,
+            vec!["session", "pane_text", "history", "last_command"]
+        );
+        assert!(exchanges.is_empty());
+        assert_eq!(vars["user_input"], "why did the tests fail?");
+        assert!(estimator.messages(&messages) <= 22);
+    }
+}
@@ -0,0 +1,243 @@
+use serde::Deserialize;
+use std::{collections::HashMap, env, fs, io, path::PathBuf, process::Command};
+
+use crate::context::{Backend, PaneConfig};
+use crate::llm::LLMError;
+use crate::redact::RedactionConfig;
+use crate::safety::DangerPolicy;
+use crate::usage::{BudgetConfig, Price};
+
+// env
+const ENV_CONFIG: &str = "ASK_SH_CONFIG";
+const ENV_XDG_CONFIG_HOME: &str = "XDG_CONFIG_HOME";
+
+/// Contents of ~/.config/ask-sh/config.toml
+///
+/// ```toml
+/// default_profile = "local"
+///
+/// [profiles.local]
+/// provider = "openai"
+/// base_url = "http://localhost:11434/v1"
+/// model = "qwen2.5-coder"
+/// api_key = "ollama"
+///
+/// [profiles.strong]
+/// provider = "anthropic"
+/// model = "claude-3-5-sonnet-latest"
+/// api_key_command = "pass show anthropic"
+/// max_tokens = 8192
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    /// Extra secret patterns, applied whatever the profile
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Scrollback depth and cleaning of the captured terminal text
+    #[serde(default)]
+    pub pane: PaneConfig,
+    /// Context windows by model name prefix, over the built-in table
+    #[serde(default)]
+    pub context_windows: HashMap<String, usize>,
+    /// USD per million tokens by model name prefix, over the built-in table
+    #[serde(default)]
+    pub prices: HashMap<String, Price>,
+    /// Monthly spending limit
+    #[serde(default)]
+    pub budget: BudgetConfig,
+}
+
+/// A named set of settings. Every field is optional; env vars override them.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub provider: Option<String>,
+    pub model: Option<String>,
+    pub base_url: Option<String>,
+    pub api_key: Option<String>,
+    /// Shell command printing the API key, e.g. `pass show openai`
+    pub api_key_command: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    /// Context window size (Ollama only)
+    pub num_ctx: Option<u32>,
+    /// How long the model stays loaded, e.g. "10m" or "-1" (Ollama only)
+    pub keep_alive: Option<String>,
+    /// api-version query parameter (Azure OpenAI only)
+    pub api_version: Option<String>,
+    /// Ask for the token usage in the stream (OpenAI only; default: true without a base_url)
+    pub stream_usage: Option<bool>,
+    pub no_pane: Option<bool>,
+    pub no_suggest: Option<bool>,
+    /// Where the terminal contents come from: "tmux", "screen", "zellij", "kitty" or "wezterm"
+    pub context_source: Option<Backend>,
+    /// Do not send the recent shell history
+    pub no_history: Option<bool>,
+    /// What to do with dangerous commands: "warn", "confirm" or "block"
+    pub danger_policy: Option<DangerPolicy>,
+    #[serde(rename = "continue")]
+    pub continue_session: Option<bool>,
+    /// Retries of a provider that is rate limited, overloaded or down (default: 2)
+    pub max_retries: Option<u32>,
+    /// Profiles tried in order when the provider of this one fails
+    pub fallback: Option<Vec<String>>,
+}
+
+impl Profile {
+    /// Returns the API key, running `api_key_command` if needed
+    pub fn api_key(&self) -> Result<Option<String>, LLMError> {
+        if let Some(api_key) = &self.api_key {
+            return Ok(Some(api_key.clone()));
+        }
+        let command = match &self.api_key_command {
+            Some(command) => command,
+            None => return Ok(None),
+        };
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .map_err(|e| LLMError::ConfigError(format!("api_key_command failed: {}", e)))?;
+        if !output.status.success() {
+            return Err(LLMError::ConfigError(format!(
+                "api_key_command exited with {}",
+                output.status
+            )));
+        }
+        let api_key = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(Some(api_key).filter(|key| !key.is_empty()))
+    }
+}
+
+impl ConfigFile {
+    /// $ASK_SH_CONFIG, or $XDG_CONFIG_HOME/ask-sh/config.toml (~/.config/ask-sh/config.toml)
+    pub fn path() -> Option<PathBuf> {
+        if let Ok(path) = env::var(ENV_CONFIG) {
+            return Some(PathBuf::from(path));
+        }
+        let config_home = match env::var(ENV_XDG_CONFIG_HOME) {
+            Ok(value) if !value.is_empty() => PathBuf::from(value),
+            _ => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+        };
+        Some(config_home.join("ask-sh").join("config.toml"))
+    }
+
+    /// Load the config file. A missing file is an empty config.
+    pub fn load() -> Result<Self, LLMError> {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return Ok(Self::default()),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|e| LLMError::ConfigError(format!("{}: {}", path.display(), e))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(LLMError::ConfigError(format!("{}: {}", path.display(), e))),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// Select the profile named `name`, falling back to `default_profile`.
+    /// Without either, an empty profile is used.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile, LLMError> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .cloned()
+                .ok_or_else(|| LLMError::ConfigError(format!("Unknown profile: {}", name))),
+            None => Ok(Profile::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::BudgetAction;
+
+    const CONFIG: &str = r#"
+default_profile = "local"
+
+[profiles.local]
+provider = "openai"
+base_url = "http://localhost:11434/v1"
+model = "qwen2.5-coder"
+api_key = "ollama"
+no_pane = true
+
+[redaction]
+patterns = ["corp-[0-9]{6}"]
+
+[pane]
+scrollback = 500
+join_wrapped = false
+
+[context_windows]
+"qwen2.5-coder" = 32768
+
+[prices]
+"qwen2.5-coder" = { input = 0.0, output = 0.0 }
+
+[budget]
+monthly = 20.0
+action = "refuse"
+
+[profiles.strong]
+provider = "anthropic"
+api_key_command = "echo secret"
+max_tokens = 8192
+temperature = 0.2
+continue = true
+danger_policy = "block"
+max_retries = 4
+fallback = ["local"]
+"#;
+
+    #[test]
+    fn test_default_and_named_profiles() {
+        let config = ConfigFile::parse(CONFIG).unwrap();
+
+        let local = config.profile(None).unwrap();
+        assert_eq!(local.provider.as_deref(), Some("openai"));
+        assert_eq!(local.no_pane, Some(true));
+        assert_eq!(local.api_key().unwrap().as_deref(), Some("ollama"));
+
+        let strong = config.profile(Some("strong")).unwrap();
+        assert_eq!(strong.max_tokens, Some(8192));
+        assert_eq!(strong.continue_session, Some(true));
+        assert_eq!(strong.danger_policy, Some(DangerPolicy::Block));
+        assert_eq!(strong.max_retries, Some(4));
+        assert_eq!(strong.fallback, Some(vec!["local".to_string()]));
+        assert_eq!(strong.api_key().unwrap().as_deref(), Some("secret"));
+
+        assert!(config.profile(Some("missing")).is_err());
+        assert_eq!(config.redaction.patterns, vec!["corp-[0-9]{6}"]);
+        assert_eq!(config.pane.scrollback, Some(500));
+        assert_eq!(config.pane.join_wrapped, Some(false));
+        assert_eq!(config.context_windows["qwen2.5-coder"], 32768);
+        assert_eq!(config.prices["qwen2.5-coder"].output, 0.0);
+        assert_eq!(config.budget.monthly, Some(20.0));
+        assert_eq!(config.budget.action, Some(BudgetAction::Refuse));
+    }
+
+    #[test]
+    fn test_empty_config() {
+        let config = ConfigFile::parse("").unwrap();
+        let profile = config.profile(None).unwrap();
+        assert!(profile.provider.is_none());
+        assert_eq!(profile.api_key().unwrap(), None);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        assert!(ConfigFile::parse("[profiles.a]\nmodle = \"typo\"\n").is_err());
+    }
+}
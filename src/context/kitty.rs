@@ -0,0 +1,53 @@
+//! kitty: `kitty @ get-text` prints a window, with `allow_remote_control` enabled
+
+use std::io;
+
+use super::{CommandRunner, ContextSource};
+
+#[derive(Debug)]
+pub struct KittySource {
+    /// `$KITTY_WINDOW_ID` of the shell; without it kitty uses the active window
+    window_id: Option<String>,
+}
+
+impl KittySource {
+    pub fn new(window_id: Option<String>) -> Self {
+        Self { window_id }
+    }
+}
+
+impl ContextSource for KittySource {
+    fn name(&self) -> &'static str {
+        "kitty"
+    }
+
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String> {
+        match &self.window_id {
+            Some(id) => runner.run(
+                "kitty",
+                &["@", "get-text", "--match", &format!("id:{}", id)],
+            ),
+            None => runner.run("kitty", &["@", "get-text"]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::testing::FakeRunner;
+
+    #[test]
+    fn test_get_text_of_own_window() {
+        let runner = FakeRunner::new("$ pwd\n/home/me\n");
+        let text = KittySource::new(Some("3".to_string()))
+            .capture(&runner)
+            .unwrap();
+        assert_eq!(text, "$ pwd\n/home/me\n");
+        KittySource::new(None).capture(&runner).unwrap();
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec!["kitty @ get-text --match id:3", "kitty @ get-text"]
+        );
+    }
+}
@@ -0,0 +1,344 @@
+//! Capture of the terminal contents sent as context, one source per multiplexer or terminal
+
+use serde::Deserialize;
+use std::{
+    env, fmt,
+    fmt::Debug,
+    fs, io,
+    os::unix::fs::DirBuilderExt,
+    path::{Path, PathBuf},
+    process::{self, Command},
+    str::FromStr,
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::budget::Estimator;
+
+pub mod kitty;
+pub mod screen;
+pub mod tmux;
+pub mod trim;
+pub mod wezterm;
+pub mod zellij;
+
+/// Runs the commands of the sources, replaced by a fake in tests
+pub trait CommandRunner {
+    /// Returns the stdout of `program`, or an error when it cannot run or exits with a failure
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String>;
+}
+
+/// Runs the commands for real
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+        let output = Command::new(program).args(args).output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "{} exited with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+}
+
+pub trait ContextSource: Debug {
+    /// Returns the backend name
+    fn name(&self) -> &'static str;
+
+    /// Returns the text of the pane or window the user is typing in
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String>;
+}
+
+/// `[pane]` table of the config file
+///
+/// ```toml
+/// [pane]
+/// scrollback = 500
+/// max_tokens = 4000
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PaneConfig {
+    /// Lines of history above the visible screen (tmux only, default: 0)
+    pub scrollback: Option<u32>,
+    /// Join lines wrapped by the terminal width (tmux only, default: true)
+    pub join_wrapped: Option<bool>,
+    /// Remove color and other escape sequences (default: true)
+    pub strip_ansi: Option<bool>,
+    /// Replace runs of identical lines with a count (default: true)
+    pub collapse_repeats: Option<bool>,
+    /// Budget for the captured text, estimated like the rest of the request (default: 2000)
+    pub max_tokens: Option<usize>,
+}
+
+const DEFAULT_PANE_MAX_TOKENS: usize = 2000;
+
+/// Where the terminal contents come from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Tmux,
+    Screen,
+    Zellij,
+    Kitty,
+    WezTerm,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tmux" => Ok(Backend::Tmux),
+            "screen" => Ok(Backend::Screen),
+            "zellij" => Ok(Backend::Zellij),
+            "kitty" => Ok(Backend::Kitty),
+            "wezterm" => Ok(Backend::WezTerm),
+            other => Err(format!(
+                "Unknown context source: {} (expected tmux, screen, zellij, kitty or wezterm)",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Tmux => write!(f, "tmux"),
+            Backend::Screen => write!(f, "screen"),
+            Backend::Zellij => write!(f, "zellij"),
+            Backend::Kitty => write!(f, "kitty"),
+            Backend::WezTerm => write!(f, "wezterm"),
+        }
+    }
+}
+
+impl Backend {
+    /// The backend whose variable is set by `var`. Multiplexers come first as they run
+    /// inside the terminal, and their pane is the one the user is typing in.
+    pub fn detect(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        [
+            ("TMUX", Backend::Tmux),
+            ("ZELLIJ", Backend::Zellij),
+            ("STY", Backend::Screen),
+            ("KITTY_WINDOW_ID", Backend::Kitty),
+            ("WEZTERM_PANE", Backend::WezTerm),
+        ]
+        .into_iter()
+        .find(|(key, _)| var(key).is_some_and(|value| !value.is_empty()))
+        .map(|(_, backend)| backend)
+    }
+}
+
+pub fn create_source(backend: Backend, config: &PaneConfig) -> Box<dyn ContextSource> {
+    match backend {
+        Backend::Tmux => Box::new(tmux::TmuxSource::new(
+            config.scrollback.unwrap_or(0),
+            config.join_wrapped.unwrap_or(true),
+        )),
+        Backend::Screen => Box::new(screen::ScreenSource::new(
+            dump_path("screen"),
+            env::var("WINDOW").ok(),
+        )),
+        Backend::Zellij => Box::new(zellij::ZellijSource::new(dump_path("zellij"))),
+        Backend::Kitty => Box::new(kitty::KittySource::new(env::var("KITTY_WINDOW_ID").ok())),
+        Backend::WezTerm => Box::new(wezterm::WezTermSource),
+    }
+}
+
+/// Clean the captured text and trim it to the token budget of `config`
+pub fn prepare(
+    text: &str,
+    config: &PaneConfig,
+    last_command: Option<&str>,
+    estimator: &Estimator,
+) -> String {
+    let text = if config.strip_ansi.unwrap_or(true) {
+        trim::strip_ansi(text)
+    } else {
+        text.to_string()
+    };
+    let mut lines: Vec<String> = text
+        .lines()
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    trim::drop_current_prompt(&mut lines);
+    if config.collapse_repeats.unwrap_or(true) {
+        lines = trim::collapse_repeats(lines);
+    }
+    let max_tokens = config.max_tokens.unwrap_or(DEFAULT_PANE_MAX_TOKENS);
+    trim::trim_to_budget(lines, max_tokens, last_command, estimator).join("\n")
+}
+
+/// Temporary file for the sources that dump to a file instead of stdout, in a directory of
+/// its own under $XDG_RUNTIME_DIR (or the temp dir). The directory is made by `create_dump_dir`.
+fn dump_path(name: &str) -> PathBuf {
+    let base = env::var_os("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .unwrap_or_else(env::temp_dir);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    base.join(format!("ask-sh-{}-{}-{}", name, process::id(), nanos))
+        .join("dump.txt")
+}
+
+/// Create the directory of a dump, readable by the user only. It must not exist yet, so
+/// nobody else can have put a symlink at the path of the dump.
+fn create_dump_dir(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::DirBuilder::new().mode(0o700).create(dir),
+        None => Ok(()),
+    }
+}
+
+/// Read and remove a dump and its directory. The multiplexer may write it after the command
+/// returned.
+fn read_dump(path: &Path) -> io::Result<String> {
+    for _ in 0..20 {
+        if path.exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(25));
+    }
+    let text = fs::read(path);
+    remove_dump(path);
+    Ok(String::from_utf8_lossy(&text?).to_string())
+}
+
+/// Remove a dump and its directory, e.g. when the multiplexer failed
+fn remove_dump(path: &Path) {
+    let _ = fs::remove_file(path);
+    if let Some(dir) = path.parent() {
+        let _ = fs::remove_dir(dir);
+    }
+}
+
+#[cfg(test)]
+pub mod testing {
+    use super::*;
+    use std::cell::RefCell;
+
+    /// Returns `output`, or writes it to `dump` like `screen -X hardcopy` does
+    pub struct FakeRunner {
+        pub output: String,
+        pub dump: Option<PathBuf>,
+        /// Command lines run so far
+        pub calls: RefCell<Vec<String>>,
+    }
+
+    impl FakeRunner {
+        pub fn new(output: &str) -> Self {
+            Self {
+                output: output.to_string(),
+                dump: None,
+                calls: RefCell::new(Vec::new()),
+            }
+        }
+    }
+
+    impl CommandRunner for FakeRunner {
+        fn run(&self, program: &str, args: &[&str]) -> io::Result<String> {
+            self.calls
+                .borrow_mut()
+                .push(format!("{} {}", program, args.join(" ")));
+            match &self.dump {
+                Some(path) => fs::write(path, &self.output).map(|_| String::new()),
+                None => Ok(self.output.clone()),
+            }
+        }
+    }
+
+    pub fn temp_dump(name: &str) -> PathBuf {
+        env::temp_dir()
+            .join(format!("ask-sh-test-{}-{}", name, process::id()))
+            .join("dump.txt")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn detect(vars: &[(&str, &str)]) -> Option<Backend> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Backend::detect(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn test_detect_innermost_first() {
+        assert_eq!(detect(&[]), None);
+        assert_eq!(detect(&[("WEZTERM_PANE", "0")]), Some(Backend::WezTerm));
+        assert_eq!(
+            detect(&[
+                ("KITTY_WINDOW_ID", "1"),
+                ("TMUX", "/tmp/tmux-0/default,1,0")
+            ]),
+            Some(Backend::Tmux)
+        );
+        assert_eq!(
+            detect(&[("STY", "123.pts-0.host"), ("KITTY_WINDOW_ID", "1")]),
+            Some(Backend::Screen)
+        );
+        assert_eq!(detect(&[("TMUX", "")]), None);
+    }
+
+    #[test]
+    fn test_backend_names() {
+        for name in ["tmux", "screen", "zellij", "kitty", "wezterm"] {
+            let backend: Backend = name.parse().unwrap();
+            assert_eq!(backend.to_string(), name);
+            assert_eq!(create_source(backend, &PaneConfig::default()).name(), name);
+        }
+        assert!("konsole".parse::<Backend>().is_err());
+    }
+
+    #[test]
+    fn test_dump_dir_is_private_and_new() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dump = testing::temp_dump("private");
+        create_dump_dir(&dump).unwrap();
+        let dir = dump.parent().unwrap();
+        assert_eq!(
+            fs::metadata(dir).unwrap().permissions().mode() & 0o777,
+            0o700
+        );
+        // a directory made by someone else is not used
+        assert!(create_dump_dir(&dump).is_err());
+        fs::write(&dump, "text").unwrap();
+        assert_eq!(read_dump(&dump).unwrap(), "text");
+        assert!(!dir.exists());
+        assert_ne!(dump_path("screen"), dump_path("zellij"));
+    }
+
+    #[test]
+    fn test_prepare() {
+        let estimator = Estimator::for_model("openai", "gpt-4");
+        let config = PaneConfig::default();
+        let text =
+            "\x1b[32m$ cargo build\x1b[0m   \nCompiling\nCompiling\nerror[E0308]\n$ ask why\n\n";
+        assert_eq!(
+            prepare(text, &config, None, &estimator),
+            "$ cargo build\nCompiling\n[previous line repeated once]\nerror[E0308]"
+        );
+        let config = PaneConfig {
+            collapse_repeats: Some(false),
+            strip_ansi: Some(false),
+            ..PaneConfig::default()
+        };
+        assert!(prepare(text, &config, None, &estimator)
+            .starts_with("\x1b[32m$ cargo build\x1b[0m\nCompiling\nCompiling\n"));
+    }
+}
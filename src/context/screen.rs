@@ -0,0 +1,68 @@
+//! GNU screen: `screen -p <window> -X hardcopy <file>` writes a window to a file
+
+use std::{io, path::PathBuf};
+
+use super::{create_dump_dir, read_dump, remove_dump, CommandRunner, ContextSource};
+
+#[derive(Debug)]
+pub struct ScreenSource {
+    /// Absolute, as screen resolves relative paths from its own directory
+    dump: PathBuf,
+    /// `$WINDOW` of the shell; without it screen dumps the current window of the session
+    window: Option<String>,
+}
+
+impl ScreenSource {
+    pub fn new(dump: PathBuf, window: Option<String>) -> Self {
+        Self { dump, window }
+    }
+}
+
+impl ContextSource for ScreenSource {
+    fn name(&self) -> &'static str {
+        "screen"
+    }
+
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String> {
+        create_dump_dir(&self.dump)?;
+        let dump = self.dump.to_string_lossy();
+        let result = match &self.window {
+            Some(window) => runner.run("screen", &["-p", window, "-X", "hardcopy", &dump]),
+            None => runner.run("screen", &["-X", "hardcopy", &dump]),
+        };
+        if let Err(e) = result {
+            remove_dump(&self.dump);
+            return Err(e);
+        }
+        read_dump(&self.dump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::testing::{temp_dump, FakeRunner};
+
+    #[test]
+    fn test_hardcopy() {
+        let dump = temp_dump("screen");
+        let mut runner = FakeRunner::new("$ ls\nREADME.md\n");
+        runner.dump = Some(dump.clone());
+        let text = ScreenSource::new(dump.clone(), Some("2".to_string()))
+            .capture(&runner)
+            .unwrap();
+        assert_eq!(text, "$ ls\nREADME.md\n");
+        ScreenSource::new(dump.clone(), None)
+            .capture(&runner)
+            .unwrap();
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![
+                format!("screen -p 2 -X hardcopy {}", dump.display()),
+                format!("screen -X hardcopy {}", dump.display())
+            ]
+        );
+        assert!(!dump.exists());
+        assert!(!dump.parent().unwrap().exists());
+    }
+}
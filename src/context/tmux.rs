@@ -0,0 +1,58 @@
+//! tmux: `tmux capture-pane -p` prints the current pane, `-S -N` adds N lines of scrollback
+
+use std::io;
+
+use super::{CommandRunner, ContextSource};
+
+#[derive(Debug)]
+pub struct TmuxSource {
+    /// Lines above the visible screen
+    scrollback: u32,
+    /// Join wrapped lines (`-J`)
+    join_wrapped: bool,
+}
+
+impl TmuxSource {
+    pub fn new(scrollback: u32, join_wrapped: bool) -> Self {
+        Self {
+            scrollback,
+            join_wrapped,
+        }
+    }
+}
+
+impl ContextSource for TmuxSource {
+    fn name(&self) -> &'static str {
+        "tmux"
+    }
+
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String> {
+        let start = format!("-{}", self.scrollback);
+        let mut args = vec!["capture-pane", "-p"];
+        if self.join_wrapped {
+            args.push("-J");
+        }
+        if self.scrollback > 0 {
+            args.extend(["-S", &start]);
+        }
+        runner.run("tmux", &args)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::testing::FakeRunner;
+
+    #[test]
+    fn test_capture_pane() {
+        let runner = FakeRunner::new("$ make\nerror: missing separator\n");
+        let text = TmuxSource::new(0, false).capture(&runner).unwrap();
+        assert_eq!(text, "$ make\nerror: missing separator\n");
+        TmuxSource::new(300, true).capture(&runner).unwrap();
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec!["tmux capture-pane -p", "tmux capture-pane -p -J -S -300"]
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Cleaning of the captured text: escapes, repeated lines, the current prompt, and trimming
+//! to a token budget that keeps the most recent command and its output
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+use crate::budget::Estimator;
+
+/// CSI and OSC sequences, and the remaining single character escapes
+static ANSI_ESCAPE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\x1b(?:\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b]*(?:\x07|\x1b\\)|[@-Z\\-_])").unwrap()
+});
+
+/// A line where a command was typed: `user@host:~/src$ make`, `[me@host src]# make`,
+/// `~/src ❯ make`, `% make`, `(venv) $ make`. `#` only counts after a user or a path in
+/// brackets, so `# comment` and `100% done` in the output are not taken for prompts.
+static PROMPT_LINE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?:\(\S+\)\s+)?(?:\S+@\S+[$%#]|\[[^\]]+\][$%#]|[~/]\S*\s?[$%❯➜»]|[$%❯➜»])\s+\S")
+        .unwrap()
+});
+
+pub fn strip_ansi(text: &str) -> String {
+    ANSI_ESCAPE.replace_all(text, "").into_owned()
+}
+
+/// Replace runs of identical lines, like progress output, with one line and a count
+pub fn collapse_repeats(lines: Vec<String>) -> Vec<String> {
+    let mut collapsed: Vec<String> = Vec::new();
+    let mut repeats = 0;
+    for line in lines {
+        if collapsed.last() == Some(&line) {
+            // blank lines are only squeezed
+            if !line.is_empty() {
+                repeats += 1;
+            }
+            continue;
+        }
+        if repeats > 0 {
+            collapsed.push(repeat_marker(repeats));
+            repeats = 0;
+        }
+        collapsed.push(line);
+    }
+    if repeats > 0 {
+        collapsed.push(repeat_marker(repeats));
+    }
+    collapsed
+}
+
+fn repeat_marker(repeats: usize) -> String {
+    match repeats {
+        1 => "[previous line repeated once]".to_string(),
+        _ => format!("[previous line repeated {} more times]", repeats),
+    }
+}
+
+/// Drop trailing blank lines and the line `ask` was typed on, which is always the last one
+pub fn drop_current_prompt(lines: &mut Vec<String>) {
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    lines.pop();
+}
+
+/// Index of the line the last command was typed on. The command recorded by the shell hooks
+/// is looked for first, after a prompt; without it, the last line that looks like a prompt.
+fn last_command_line(lines: &[String], recorded: Option<&str>) -> Option<usize> {
+    let recorded = recorded
+        .and_then(|command| command.lines().next())
+        .map(str::trim)
+        .filter(|command| !command.is_empty());
+    recorded
+        .and_then(|command| {
+            lines.iter().rposition(|line| {
+                line.strip_suffix(command).is_some_and(|prompt| {
+                    prompt.ends_with(char::is_whitespace) && !prompt.trim().is_empty()
+                })
+            })
+        })
+        .or_else(|| lines.iter().rposition(|line| PROMPT_LINE.is_match(line)))
+}
+
+/// Tokens of a line, with its newline
+fn line_tokens(line: &str, estimator: &Estimator) -> usize {
+    estimator.tokens(line) + 1
+}
+
+fn total_tokens(lines: &[String], estimator: &Estimator) -> usize {
+    lines.iter().map(|line| line_tokens(line, estimator)).sum()
+}
+
+/// Start of the longest suffix of `lines` that fits in `budget`
+fn fitting_suffix(lines: &[String], mut budget: usize, estimator: &Estimator) -> usize {
+    let mut start = lines.len();
+    while start > 0 && line_tokens(&lines[start - 1], estimator) <= budget {
+        budget -= line_tokens(&lines[start - 1], estimator);
+        start -= 1;
+    }
+    start
+}
+
+/// Room left for the `[... N lines trimmed ...]` marker
+const MARKER_TOKENS: usize = 10;
+
+/// Keep the last command with its output, then as many earlier lines as fit in `max_tokens`.
+/// When the last command alone is too long, keep its first line and the end of its output.
+/// `recorded` is the last command as recorded by the shell hooks, if any.
+pub fn trim_to_budget(
+    lines: Vec<String>,
+    max_tokens: usize,
+    recorded: Option<&str>,
+    estimator: &Estimator,
+) -> Vec<String> {
+    if total_tokens(&lines, estimator) <= max_tokens {
+        return lines;
+    }
+
+    let last_command = last_command_line(&lines, recorded).unwrap_or(0);
+    let (earlier, recent) = lines.split_at(last_command);
+    let budget = max_tokens.saturating_sub(MARKER_TOKENS);
+
+    if total_tokens(recent, estimator) > budget {
+        let output = &recent[1..];
+        let start = fitting_suffix(
+            output,
+            budget.saturating_sub(line_tokens(&recent[0], estimator)),
+            estimator,
+        );
+        let mut kept = vec![recent[0].clone()];
+        kept.push(format!("[... {} lines trimmed ...]", start));
+        kept.extend_from_slice(&output[start..]);
+        return kept;
+    }
+
+    let start = fitting_suffix(earlier, budget - total_tokens(recent, estimator), estimator);
+    let mut kept = Vec::new();
+    if start > 0 {
+        kept.push(format!("[... {} earlier lines trimmed ...]", start));
+    }
+    kept.extend_from_slice(&earlier[start..]);
+    kept.extend_from_slice(recent);
+    kept
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpt() -> Estimator {
+        Estimator::for_model("openai", "gpt-4")
+    }
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_strip_ansi_and_collapse() {
+        assert_eq!(
+            strip_ansi("\x1b[1;31merror\x1b[0m: \x1b]0;title\x07failed"),
+            "error: failed"
+        );
+        assert_eq!(
+            collapse_repeats(lines("a\nwaiting\nwaiting\nwaiting\nb\n\n\nc\nc")),
+            lines("a\nwaiting\n[previous line repeated 2 more times]\nb\n\nc\n[previous line repeated once]")
+        );
+    }
+
+    #[test]
+    fn test_drop_current_prompt() {
+        let mut text = lines("$ ls\nfile\nme@host:~$ ask what is this\n\n");
+        drop_current_prompt(&mut text);
+        assert_eq!(text, lines("$ ls\nfile"));
+        // a prompt that does not look like one is still dropped
+        let mut text = lines("$ ls\nfile\n> ask\n");
+        drop_current_prompt(&mut text);
+        assert_eq!(text, lines("$ ls\nfile"));
+    }
+
+    #[test]
+    fn test_prompt_lines() {
+        for line in [
+            "$ make",
+            "me@host:~/src$ make",
+            "root@host:/etc# make",
+            "[me@host src]$ make",
+            "~/src ❯ make",
+            "% make",
+            "(venv) $ make",
+        ] {
+            assert!(PROMPT_LINE.is_match(line), "{}", line);
+        }
+        for line in [
+            "# comment",
+            "100% done",
+            "  - name: $ build",
+            "total: 5$ spent",
+        ] {
+            assert!(!PROMPT_LINE.is_match(line), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_trim_finds_the_recorded_command() {
+        let mut text = lines("$ echo old\nold\nprompt> cat config.yaml");
+        text.extend((0..50).map(|i| format!("# comment {}\nkey: 100% done", i)));
+        let text: Vec<String> = text.join("\n").lines().map(str::to_string).collect();
+
+        let trimmed = trim_to_budget(text.clone(), 100, Some("cat config.yaml"), &gpt());
+        assert_eq!(trimmed[0], "prompt> cat config.yaml");
+        assert_eq!(trimmed.last().unwrap(), "key: 100% done");
+
+        // an output line that only ends with the command is not taken for it
+        let mut text = text;
+        text.push("cat config.yaml".to_string());
+        let trimmed = trim_to_budget(text, 100, Some("cat config.yaml"), &gpt());
+        assert_eq!(trimmed[0], "prompt> cat config.yaml");
+    }
+
+    #[test]
+    fn test_trim_keeps_the_last_command() {
+        let mut text = lines("$ echo old\nold 1\nold 2\nold 3\nold 4\nold 5\n$ make");
+        text.extend((0..100).map(|i| format!("error line {}", i)));
+
+        let trimmed = trim_to_budget(text.clone(), 1000, None, &gpt());
+        assert_eq!(trimmed, text);
+
+        // the last command fits, the earlier lines do not
+        let trimmed = trim_to_budget(text.clone(), 508, None, &gpt());
+        assert_eq!(trimmed[0], "[... 5 earlier lines trimmed ...]");
+        assert_eq!(trimmed[1], "old 5");
+        assert_eq!(trimmed[2], "$ make");
+        assert_eq!(trimmed.last().unwrap(), "error line 99");
+
+        // the last command alone is too long: keep its first line and the end of its output
+        let trimmed = trim_to_budget(text, 40, None, &gpt());
+        assert_eq!(trimmed[0], "$ make");
+        assert!(trimmed[1].starts_with("[... ") && trimmed[1].ends_with(" lines trimmed ...]"));
+        assert_eq!(trimmed.last().unwrap(), "error line 99");
+        assert!(
+            trimmed
+                .iter()
+                .map(|line| gpt().tokens(line) + 1)
+                .sum::<usize>()
+                <= 45
+        );
+    }
+}
@@ -0,0 +1,31 @@
+//! WezTerm: `wezterm cli get-text` prints the pane named by `$WEZTERM_PANE`
+
+use std::io;
+
+use super::{CommandRunner, ContextSource};
+
+#[derive(Debug)]
+pub struct WezTermSource;
+
+impl ContextSource for WezTermSource {
+    fn name(&self) -> &'static str {
+        "wezterm"
+    }
+
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String> {
+        runner.run("wezterm", &["cli", "get-text"])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::testing::FakeRunner;
+
+    #[test]
+    fn test_get_text() {
+        let runner = FakeRunner::new("$ whoami\nme\n");
+        assert_eq!(WezTermSource.capture(&runner).unwrap(), "$ whoami\nme\n");
+        assert_eq!(*runner.calls.borrow(), vec!["wezterm cli get-text"]);
+    }
+}
@@ -0,0 +1,59 @@
+//! Zellij: `zellij action dump-screen <file>` writes the focused pane to a file
+
+use std::{io, path::PathBuf};
+
+use super::{create_dump_dir, read_dump, remove_dump, CommandRunner, ContextSource};
+
+#[derive(Debug)]
+pub struct ZellijSource {
+    dump: PathBuf,
+}
+
+impl ZellijSource {
+    pub fn new(dump: PathBuf) -> Self {
+        Self { dump }
+    }
+}
+
+impl ContextSource for ZellijSource {
+    fn name(&self) -> &'static str {
+        "zellij"
+    }
+
+    fn capture(&self, runner: &dyn CommandRunner) -> io::Result<String> {
+        create_dump_dir(&self.dump)?;
+        let dump = self.dump.to_string_lossy();
+        let result = runner.run("zellij", &["action", "dump-screen", &dump]);
+        if let Err(e) = result {
+            remove_dump(&self.dump);
+            return Err(e);
+        }
+        read_dump(&self.dump)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::testing::{temp_dump, FakeRunner};
+
+    #[test]
+    fn test_dump_screen() {
+        let dump = temp_dump("zellij");
+        let mut runner = FakeRunner::new("$ cargo test\ntest result: FAILED\n");
+        runner.dump = Some(dump.clone());
+        let text = ZellijSource::new(dump.clone()).capture(&runner).unwrap();
+        assert_eq!(text, "$ cargo test\ntest result: FAILED\n");
+        assert_eq!(
+            *runner.calls.borrow(),
+            vec![format!("zellij action dump-screen {}", dump.display())]
+        );
+    }
+
+    #[test]
+    fn test_missing_dump_is_an_error() {
+        let dump = temp_dump("zellij-missing");
+        let runner = FakeRunner::new("");
+        assert!(ZellijSource::new(dump).capture(&runner).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+//! Extraction of runnable commands from the Markdown answer of the LLM.
+//!
+//! Fenced (```` ``` ```` and `~~~`) and indented code blocks are parsed following CommonMark,
+//! keeping their info string and line structure. Inline code spans are picked up too,
+//! as long as they look like a command line.
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// Info strings of code blocks holding shell commands. Blocks without one count as shell.
+const SHELL_LANGUAGES: &[&str] = &[
+    "sh",
+    "bash",
+    "zsh",
+    "fish",
+    "ksh",
+    "shell",
+    "shellscript",
+    "console",
+    "shell-session",
+    "nu",
+    "nushell",
+];
+
+/// Info strings of transcripts, where commands follow a `$ ` prompt and output is interleaved
+const TRANSCRIPT_LANGUAGES: &[&str] = &["console", "shell-session"];
+
+/// `<<EOF`, `<<-EOF` or `<< 'EOF'`, but not the here-string `<<<`
+static HEREDOC: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"(^|[^<])<<-?\s*['"]?[A-Za-z_][A-Za-z0-9_]*"#).unwrap());
+
+/// How a piece of code was marked up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// Fenced code block
+    Fenced,
+    /// Code block indented by four spaces
+    Indented,
+    /// Inline code span delimited by this many backticks
+    Span(usize),
+}
+
+/// A piece of code found in the answer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub kind: BlockKind,
+    /// First word of the info string, e.g. `bash`
+    pub language: Option<String>,
+    pub code: String,
+    /// Paragraph containing a code span, or preceding a code block
+    pub explanation: String,
+}
+
+/// A command suggested by the LLM
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuggestedCommand {
+    /// The command as it should be typed. May span several lines.
+    pub text: String,
+    pub language: Option<String>,
+    /// The prose introducing the command
+    pub explanation: String,
+}
+
+impl SuggestedCommand {
+    /// The command joined into a single line for the line editor.
+    /// Returns `None` for scripts that cannot be joined, i.e. containing a heredoc.
+    pub fn one_line(&self) -> Option<String> {
+        if HEREDOC.is_match(&self.text) {
+            return None;
+        }
+
+        // join `\` continuations and drop blank and comment lines
+        let mut logical_lines = Vec::new();
+        let mut current = String::new();
+        for line in self.text.lines() {
+            let line = line.trim();
+            if current.is_empty() && (line.is_empty() || line.starts_with('#')) {
+                continue;
+            }
+            match line.strip_suffix('\\') {
+                Some(continued) => {
+                    current.push_str(continued.trim_end());
+                    current.push(' ');
+                }
+                None => {
+                    current.push_str(line);
+                    logical_lines.push(std::mem::take(&mut current));
+                }
+            }
+        }
+        if !current.trim().is_empty() {
+            logical_lines.push(current.trim_end().to_string());
+        }
+
+        let mut joined = String::new();
+        for line in logical_lines {
+            if !joined.is_empty() {
+                joined.push_str(if continues_command(&joined) {
+                    " "
+                } else {
+                    "; "
+                });
+            }
+            joined.push_str(&line);
+        }
+        Some(joined).filter(|joined| !joined.is_empty())
+    }
+}
+
+/// Whether the next line belongs to the same command, e.g. after `then` or a pipe
+fn continues_command(text: &str) -> bool {
+    if text.ends_with(['|', '&', '(', ';']) {
+        return true;
+    }
+    let last_word = text
+        .rsplit(|c: char| c.is_whitespace() || c == ';')
+        .next()
+        .unwrap_or_default();
+    matches!(last_word, "then" | "do" | "else" | "{")
+}
+
+/// An open fenced code block
+struct Fence {
+    marker: char,
+    length: usize,
+    indent: usize,
+    language: Option<String>,
+    lines: Vec<String>,
+}
+
+impl Fence {
+    fn open(line: &str) -> Option<Self> {
+        let trimmed = line.trim_start();
+        let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let length = trimmed.chars().take_while(|c| *c == marker).count();
+        if length < 3 {
+            return None;
+        }
+        let info = trimmed[length..].trim();
+        // ```ls -la``` on one line is a code span, not a fence
+        if marker == '`' && info.contains('`') {
+            return None;
+        }
+        Some(Self {
+            marker,
+            length,
+            indent: line.len() - trimmed.len(),
+            language: info.split_whitespace().next().map(|word| word.to_string()),
+            lines: Vec::new(),
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        trimmed.chars().take_while(|c| *c == self.marker).count() >= self.length
+            && trimmed.chars().all(|c| c == self.marker)
+    }
+
+    /// Content lines lose the indentation of the opening fence
+    fn push(&mut self, line: &str) {
+        let spaces = line
+            .chars()
+            .take(self.indent)
+            .take_while(|c| *c == ' ')
+            .count();
+        self.lines.push(line[spaces..].to_string());
+    }
+
+    fn finish(self, explanation: &str) -> CodeBlock {
+        CodeBlock {
+            kind: BlockKind::Fenced,
+            language: self.language,
+            code: self.lines.join("\n"),
+            explanation: explanation.to_string(),
+        }
+    }
+}
+
+fn indent_width(line: &str) -> usize {
+    line.chars()
+        .take_while(|c| c.is_whitespace())
+        .map(|c| if c == '\t' { 4 } else { 1 })
+        .sum()
+}
+
+fn is_list_item(line: &str) -> bool {
+    let line = line.trim_start();
+    if line.starts_with("- ") || line.starts_with("* ") || line.starts_with("+ ") {
+        return true;
+    }
+    let digits = line.chars().take_while(|c| c.is_ascii_digit()).count();
+    digits > 0 && (line[digits..].starts_with(". ") || line[digits..].starts_with(") "))
+}
+
+fn finish_indented(lines: &mut Vec<&str>, explanation: &str) -> CodeBlock {
+    while lines.last().is_some_and(|line| line.trim().is_empty()) {
+        lines.pop();
+    }
+    let code = lines
+        .drain(..)
+        .map(|line| {
+            line.strip_prefix('\t')
+                .or_else(|| line.strip_prefix("    "))
+                .unwrap_or(line.trim_start())
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    CodeBlock {
+        kind: BlockKind::Indented,
+        language: None,
+        code,
+        explanation: explanation.to_string(),
+    }
+}
+
+/// Ends the paragraph: collects its code spans and keeps it as explanation of what follows
+fn end_paragraph(paragraph: &mut Vec<&str>, explanation: &mut String, blocks: &mut Vec<CodeBlock>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    *explanation = paragraph
+        .iter()
+        .map(|line| line.trim())
+        .collect::<Vec<_>>()
+        .join(" ");
+    code_spans(&paragraph.join("\n"), explanation, blocks);
+    paragraph.clear();
+}
+
+/// Code spans of a paragraph. Spans may cross lines, which become spaces.
+fn code_spans(paragraph: &str, explanation: &str, blocks: &mut Vec<CodeBlock>) {
+    let chars: Vec<char> = paragraph.chars().collect();
+    let run_length = |start: usize| chars[start..].iter().take_while(|c| **c == '`').count();
+
+    let mut i = 0;
+    while i < chars.len() {
+        if chars[i] != '`' {
+            i += 1;
+            continue;
+        }
+        let length = run_length(i);
+        let start = i + length;
+        // the span ends at the next run of exactly the same length
+        let mut j = start;
+        let mut end = None;
+        while j < chars.len() {
+            if chars[j] == '`' {
+                let closing = run_length(j);
+                if closing == length {
+                    end = Some(j);
+                    break;
+                }
+                j += closing;
+            } else {
+                j += 1;
+            }
+        }
+        match end {
+            Some(end) => {
+                let code: String = chars[start..end]
+                    .iter()
+                    .map(|c| if *c == '\n' { ' ' } else { *c })
+                    .collect();
+                blocks.push(CodeBlock {
+                    kind: BlockKind::Span(length),
+                    language: None,
+                    code: code.trim().to_string(),
+                    explanation: explanation.to_string(),
+                });
+                i = end + length;
+            }
+            None => i = start,
+        }
+    }
+}
+
+/// All code blocks and code spans of `markdown`, in order
+pub fn code_blocks(markdown: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut fence: Option<Fence> = None;
+    let mut indented: Vec<&str> = Vec::new();
+    let mut paragraph: Vec<&str> = Vec::new();
+    let mut explanation = String::new();
+    let mut previous_blank = true;
+    let mut in_list = false;
+
+    for line in markdown.lines() {
+        if let Some(open) = &mut fence {
+            if open.is_closed_by(line) {
+                blocks.push(fence.take().unwrap().finish(&explanation));
+            } else {
+                open.push(line);
+            }
+            continue;
+        }
+
+        if !indented.is_empty() {
+            if line.trim().is_empty() || indent_width(line) >= 4 {
+                indented.push(line);
+                continue;
+            }
+            blocks.push(finish_indented(&mut indented, &explanation));
+        }
+
+        if let Some(open) = Fence::open(line) {
+            end_paragraph(&mut paragraph, &mut explanation, &mut blocks);
+            fence = Some(open);
+            previous_blank = false;
+            continue;
+        }
+
+        if line.trim().is_empty() {
+            end_paragraph(&mut paragraph, &mut explanation, &mut blocks);
+            previous_blank = true;
+            continue;
+        }
+
+        // an indented block cannot interrupt a paragraph, and indentation in lists is content
+        if indent_width(line) >= 4 {
+            if previous_blank && !in_list {
+                indented.push(line);
+                previous_blank = false;
+                continue;
+            }
+        } else {
+            in_list = is_list_item(line);
+        }
+        paragraph.push(line);
+        previous_blank = false;
+    }
+
+    end_paragraph(&mut paragraph, &mut explanation, &mut blocks);
+    if let Some(open) = fence {
+        // an unclosed fence runs to the end, e.g. when the answer was truncated
+        blocks.push(open.finish(&explanation));
+    }
+    if !indented.is_empty() {
+        blocks.push(finish_indented(&mut indented, &explanation));
+    }
+    blocks
+}
+
+/// Whether an inline code span such as `git status` reads as a command line.
+/// Nushell runs external programs as `^ls`; a span starting with `-` is a flag, not a program.
+fn looks_like_command(code: &str) -> bool {
+    let mut words = code.split_whitespace();
+    let program = match words.next() {
+        Some(program) => program.strip_prefix('^').unwrap_or(program),
+        None => return false,
+    };
+    words.next().is_some()
+        && !program.is_empty()
+        && !program.starts_with('-')
+        && program
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "._-/~+".contains(c))
+}
+
+/// Commands of a shell code block. Transcripts keep only the lines after a `$ ` prompt.
+fn shell_commands(language: Option<&str>, code: &str) -> String {
+    let lines: Vec<&str> = code.lines().collect();
+    let prompted = |line: &&str| line.trim_start().starts_with("$ ");
+    let is_transcript = language.is_some_and(|language| TRANSCRIPT_LANGUAGES.contains(&language))
+        || (lines.iter().any(prompted)
+            && lines
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .all(prompted));
+    if !is_transcript {
+        return code.trim_matches('\n').trim_end().to_string();
+    }
+
+    let mut commands = Vec::new();
+    let mut continued = false;
+    for line in lines {
+        if let Some(command) = line.trim_start().strip_prefix("$ ") {
+            commands.push(command);
+        } else if continued {
+            commands.push(line);
+        } else {
+            continue;
+        }
+        continued = line.trim_end().ends_with('\\');
+    }
+    commands.join("\n")
+}
+
+/// Commands in the answer, in order and without duplicates
+pub fn extract_commands(markdown: &str) -> Vec<SuggestedCommand> {
+    let mut commands: Vec<SuggestedCommand> = Vec::new();
+    for block in code_blocks(markdown) {
+        let language = block.language.map(|language| language.to_lowercase());
+        let text = match block.kind {
+            BlockKind::Fenced | BlockKind::Indented => {
+                if language
+                    .as_deref()
+                    .is_some_and(|language| !SHELL_LANGUAGES.contains(&language))
+                {
+                    continue;
+                }
+                shell_commands(language.as_deref(), &block.code)
+            }
+            // ```ls``` is a deliberate command, `ls -la` only when it reads like one
+            BlockKind::Span(length) if length >= 3 || looks_like_command(&block.code) => block.code,
+            BlockKind::Span(_) => continue,
+        };
+        if text.trim().is_empty() || commands.iter().any(|command| command.text == text) {
+            continue;
+        }
+        commands.push(SuggestedCommand {
+            text,
+            language,
+            explanation: block.explanation,
+        });
+    }
+    commands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(markdown: &str) -> Vec<String> {
+        extract_commands(markdown)
+            .into_iter()
+            .map(|command| command.text)
+            .collect()
+    }
+
+    #[test]
+    fn test_fenced_blocks_keep_language_and_lines() {
+        let markdown = "Build it:\n\n```bash\ncd project\nmake -j4\n```\n\n~~~sh\nls -la\n~~~\n";
+        let commands = extract_commands(markdown);
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].text, "cd project\nmake -j4");
+        assert_eq!(commands[0].language.as_deref(), Some("bash"));
+        assert_eq!(commands[0].one_line().unwrap(), "cd project; make -j4");
+        assert_eq!(commands[1].text, "ls -la");
+        assert_eq!(commands[0].explanation, "Build it:");
+    }
+
+    #[test]
+    fn test_non_shell_blocks_are_skipped() {
+        let markdown =
+            "```python\nprint('hi')\n```\n\n```\necho hi\n```\n````\n```\nnested\n```\n````";
+        assert_eq!(texts(markdown), vec!["echo hi", "```\nnested\n```"]);
+    }
+
+    #[test]
+    fn test_inline_spans() {
+        let markdown = "Run `git status` to check, then edit `config.toml` or run ```ls -la```.";
+        assert_eq!(texts(markdown), vec!["git status", "ls -la"]);
+
+        let markdown = "In nushell use `^ls -la`; the `-rf flag` deletes recursively.";
+        assert_eq!(texts(markdown), vec!["^ls -la"]);
+    }
+
+    #[test]
+    fn test_indented_blocks_and_lists() {
+        let markdown =
+            "Run:\n\n    docker ps\n    docker images\n\nDone.\n\n- step one\n\n      not code\n";
+        assert_eq!(texts(markdown), vec!["docker ps\ndocker images"]);
+
+        let markdown = "1. Install:\n   ```\n   brew install jq\n   ```\n";
+        assert_eq!(texts(markdown), vec!["brew install jq"]);
+    }
+
+    #[test]
+    fn test_one_line_joins_continuations_and_compound_commands() {
+        let command = SuggestedCommand {
+            text:
+                "# list\nfind . \\\n  -name '*.rs' |\n  xargs wc -l\nif true; then\n  echo ok\nfi"
+                    .to_string(),
+            language: None,
+            explanation: String::new(),
+        };
+        assert_eq!(
+            command.one_line().unwrap(),
+            "find . -name '*.rs' | xargs wc -l; if true; then echo ok; fi"
+        );
+
+        let heredoc = SuggestedCommand {
+            text: "cat <<'EOF' > notes.txt\nhello\nEOF".to_string(),
+            language: Some("bash".to_string()),
+            explanation: String::new(),
+        };
+        assert_eq!(heredoc.one_line(), None);
+        let here_string = SuggestedCommand {
+            text: "wc -w <<< 'a b'".to_string(),
+            language: None,
+            explanation: String::new(),
+        };
+        assert_eq!(here_string.one_line().unwrap(), "wc -w <<< 'a b'");
+    }
+
+    #[test]
+    fn test_transcripts_keep_prompted_lines() {
+        let markdown = "```console\n$ uname -a\nLinux host 6.1\n$ echo a \\\n  b\na b\n```";
+        assert_eq!(texts(markdown), vec!["uname -a\necho a \\\n  b"]);
+    }
+
+    #[test]
+    fn test_unclosed_fence_runs_to_end() {
+        assert_eq!(
+            texts("```\ntar czf out.tgz dir"),
+            vec!["tar czf out.tgz dir"]
+        );
+    }
+}
@@ -0,0 +1,152 @@
+//! Recent shell history sent as context, passed by the `ask` shell function in `ASK_SH_HISTORY`,
+//! and the last command recorded by the shell hooks in the file named by `ASK_SH_STATE_FILE`
+
+use std::{fs, path::Path};
+
+/// A command the user ran before asking
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub command: String,
+    /// Exit status, when the shell recorded it
+    pub status: Option<i32>,
+}
+
+/// Parse the output of `fc -ln`, oldest first. The `ask` invocation itself is dropped and
+/// `last_status` is the exit status of the command run before it.
+pub fn parse(text: &str, last_status: Option<i32>) -> Vec<HistoryEntry> {
+    let mut entries: Vec<HistoryEntry> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| HistoryEntry {
+            command: line.to_string(),
+            status: None,
+        })
+        .collect();
+    while entries
+        .last()
+        .is_some_and(|entry| entry.command.split_whitespace().next() == Some("ask"))
+    {
+        entries.pop();
+    }
+    if let Some(last) = entries.last_mut() {
+        last.status = last_status;
+    }
+    entries
+}
+
+/// One command per line, as shown to the LLM
+pub fn format(entries: &[HistoryEntry]) -> String {
+    entries
+        .iter()
+        .map(|entry| match entry.status {
+            Some(status) => format!("$ {}  (exit status {})", entry.command, status),
+            None => format!("$ {}", entry.command),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The last command run in the shell, with what the hooks could measure
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastCommand {
+    pub command: String,
+    pub status: Option<i32>,
+    /// Whole seconds
+    pub duration: Option<u64>,
+    pub cwd: Option<String>,
+}
+
+impl LastCommand {
+    /// Parse `key=value` lines. `command` comes last and keeps the rest of the file,
+    /// as the command may span several lines.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut last = LastCommand {
+            command: String::new(),
+            status: None,
+            duration: None,
+            cwd: None,
+        };
+        let mut rest = text;
+        loop {
+            if let Some(command) = rest.strip_prefix("command=") {
+                last.command = command.trim_end().to_string();
+                break;
+            }
+            let (line, next) = rest.split_once('\n')?;
+            match line.split_once('=')? {
+                ("status", value) => last.status = value.parse().ok(),
+                ("duration", value) => last.duration = value.parse().ok(),
+                ("cwd", value) => last.cwd = Some(value.to_string()).filter(|cwd| !cwd.is_empty()),
+                _ => {}
+            }
+            rest = next;
+        }
+        Some(last).filter(|last| !last.command.is_empty())
+    }
+
+    pub fn read(path: &Path) -> Option<Self> {
+        Self::parse(&fs::read_to_string(path).ok()?)
+    }
+
+    /// e.g. `` `make` exited 2 after 14s in /home/me/project ``
+    pub fn describe(&self) -> String {
+        let mut text = format!("`{}`", self.command);
+        match self.status {
+            Some(0) => text.push_str(" succeeded"),
+            Some(status) => text.push_str(&format!(" exited {}", status)),
+            None => text.push_str(" ran"),
+        }
+        if let Some(duration) = self.duration {
+            text.push_str(&format!(" after {}s", duration));
+        }
+        if let Some(cwd) = &self.cwd {
+            text.push_str(&format!(" in {}", cwd));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_drops_the_ask_invocation() {
+        let entries = parse(
+            "\t cd project\n\t cargo build\n\t ask why did that fail?\n",
+            Some(101),
+        );
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].command, "cargo build");
+        assert_eq!(entries[1].status, Some(101));
+        assert_eq!(entries[0].status, None);
+        assert_eq!(
+            format(&entries),
+            "$ cd project\n$ cargo build  (exit status 101)"
+        );
+        assert!(parse("", Some(1)).is_empty());
+    }
+
+    #[test]
+    fn test_last_command() {
+        let last =
+            LastCommand::parse("status=2\nduration=14\ncwd=/home/me/project\ncommand=make\n")
+                .unwrap();
+        assert_eq!(
+            last.describe(),
+            "`make` exited 2 after 14s in /home/me/project"
+        );
+
+        let last =
+            LastCommand::parse("status=0\ncommand=for f in *; do\n  echo $f=1\ndone\n").unwrap();
+        assert_eq!(last.command, "for f in *; do\n  echo $f=1\ndone");
+        assert_eq!(
+            last.describe(),
+            "`for f in *; do\n  echo $f=1\ndone` succeeded"
+        );
+
+        assert_eq!(LastCommand::parse("status=1\n"), None);
+        assert_eq!(LastCommand::parse("garbage"), None);
+    }
+}
@@ -0,0 +1,516 @@
+//! Shell integration printed by `ask-sh --init [bash|zsh|fish|nu]`
+
+use std::{env, path::Path, str::FromStr};
+
+/// Shell the `ask` function is generated for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Nu,
+}
+
+impl FromStr for Shell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "nu" | "nushell" => Ok(Shell::Nu),
+            other => Err(format!(
+                "Unknown shell: {} (expected bash, zsh, fish or nu)",
+                other
+            )),
+        }
+    }
+}
+
+impl Shell {
+    /// The shell named by `$SHELL`, bash when it is unset or unknown
+    pub fn detect() -> Self {
+        env::var("SHELL")
+            .ok()
+            .as_deref()
+            .and_then(Self::from_path)
+            .unwrap_or(Shell::Bash)
+    }
+
+    /// The shell of an executable path such as `/usr/bin/fish`
+    pub fn from_path(path: &str) -> Option<Self> {
+        Path::new(path).file_name()?.to_str()?.parse().ok()
+    }
+
+    pub fn init_script(&self) -> String {
+        match self {
+            Shell::Bash => [POSIX_SCRIPT, BASH_WIDGET, BASH_HOOKS].concat(),
+            Shell::Zsh => [POSIX_SCRIPT, ZSH_WIDGET, ZSH_HOOKS].concat(),
+            Shell::Fish => [FISH_SCRIPT, FISH_HOOKS].concat(),
+            Shell::Nu => NU_SCRIPT.to_string(),
+        }
+    }
+}
+
+/// Works in both bash and zsh; zsh fills the next prompt with `print -z`, bash adds to the history
+const POSIX_SCRIPT: &str = r#"# This function is automatically generated by ask-sh --init
+# ask.sh shell function v2
+ask() {
+    ask_sh_last_status=$? # exit status of the command run before ask
+    if ! command -v ask-sh &> /dev/null; then
+        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
+        printf "👉 It's usually under ~/.cargo/bin/"
+        printf "👀 Please add it to your PATH and restart your shell."
+    fi
+    ask_sh_history=""
+    if [ -z "$ASK_SH_NO_HISTORY" ]; then
+        ask_sh_history=`fc -ln -${ASK_SH_HISTORY_SIZE:-20} 2>/dev/null`
+    fi
+    suggested_commands=`echo "$@" | ASK_SH_SHELL_PID=$$ ASK_SH_HISTORY="$ask_sh_history" ASK_SH_LAST_STATUS=$ask_sh_last_status ASK_SH_STATE_FILE="$ask_sh_state_file" ask-sh 2> >(cat 1>&2)`
+    if [ -n "$suggested_commands" ]; then
+        printf "\n" # add one empty line to create space
+        printf "👋 Hey, AI has suggested some commands that can be typed into your terminal.\n"
+        printf "🔍 Press Enter to view and select the commands, or type any other key to exit:"
+        if [ -n "$ZSH_VERSION" ]; then # read a single char
+            read -r -k 1 REPLY # zsh
+        else
+            read -r -n 1 REPLY # bash
+        fi
+        REPLY="${REPLY#"${REPLY%%[![:space:]]*}"}"  # trim whitespaces
+        if [ -z "$REPLY" ] ; then
+            # As Enter will move cursor to the next line, we need to go back two lines
+            printf "\033[2A"
+            # \033[2K: delete current line (👋 line), \n: go next line, \033[2K: delete next line (🔍 line)
+            printf "\033[2K\n\033[2K\n"
+            # We're at the emptified 🔍 line. So, go back two lines, including empty line to make space
+            printf "\033[2A" # go back again
+            selected_command=`printf '%s\n' "$suggested_commands" | ask-sh pick`
+            if [ -n "$selected_command" ]; then
+                if ! print -z $selected_command 2>/dev/null; then
+                    history -s $selected_command
+                fi
+            fi
+        else
+            # We're at the end of 🔍 line. So, go back one line (👋 line)
+            printf "\033[1A"
+            printf "\033[2K\n\033[2K\n"
+            printf "\033[2A"
+        fi
+    fi
+    if [ -z "$ASK_SH_NO_UPDATE" ]; then
+        latest_version=`cargo search ask-sh | grep ask-sh | awk '{print $3}' | cut -d '"' -f2`
+        current_version=`ask-sh --version`
+        if [ "$(printf '%s\n' "$latest_version" "$current_version" | sort -rV | head -n1)" = "$latest_version" ] && [ "$latest_version" != "$current_version" ]; then
+            # clear line
+            printf "\n"
+            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
+            printf "🆙 Press Enter to run update now, or type any other key to exit:"
+            if [ -n "$ZSH_VERSION" ]; then # read a single char
+                read -r -k 1 REPLY # zsh
+            else
+                read -r -n 1 REPLY # bash
+            fi
+            REPLY="${REPLY#"${REPLY%%[![:space:]]*}"}"  # trim whitespaces
+            if [ -z "$REPLY" ] ; then
+                cargo install --force ask-sh
+                printf "\nDone! Please restart your shell or source ~/.zshrc or ~/.bashrc etc... to use the new version.\n"
+            else
+                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
+            fi
+        fi
+    fi
+}
+"#;
+
+/// ZLE widget replacing `$BUFFER` with the top suggestion
+const ZSH_WIDGET: &str = r#"
+# Ctrl-X Ctrl-A asks AI to complete or fix the command line, set $ASK_SH_KEYBINDING (bindkey syntax) to change it
+_ask_sh_widget() {
+    [ -z "$BUFFER" ] && return
+    zle -M "🤔 Asking AI about the command line..."
+    zle -R
+    local suggestion command rest warning
+    suggestion=`printf 'Complete or fix this command: %s\n' "${BUFFER//$'\n'/ }" | ASK_SH_SHELL_PID=$$ ask-sh 2>/dev/null | head -n 1`
+    if [ -z "$suggestion" ]; then
+        zle -M "😶 AI has not suggested a command."
+        return
+    fi
+    # the line is command<TAB>warning<TAB>explanation, see ask-sh pick
+    command="${suggestion%%$'\t'*}"
+    rest="${suggestion#*$'\t'}"
+    [ "$rest" != "$suggestion" ] && warning="${rest%%$'\t'*}"
+    warning="${warning% \[confirm\]}"
+    BUFFER="$command"
+    CURSOR=${#BUFFER}
+    zle -M "$warning"
+}
+if [ -n "$ZSH_VERSION" ] && [[ -o interactive ]]; then
+    zle -N ask-sh-widget _ask_sh_widget
+    if [ -n "${ASK_SH_KEYBINDING-^X^A}" ]; then
+        bindkey "${ASK_SH_KEYBINDING-^X^A}" ask-sh-widget
+    fi
+fi
+"#;
+
+/// `bind -x` equivalent of the zsh widget, replacing `$READLINE_LINE`
+const BASH_WIDGET: &str = r#"
+# Ctrl-X Ctrl-A asks AI to complete or fix the command line, set $ASK_SH_KEYBINDING (readline syntax) to change it
+_ask_sh_widget() {
+    [ -z "$READLINE_LINE" ] && return
+    local suggestion command rest warning
+    suggestion=`printf 'Complete or fix this command: %s\n' "${READLINE_LINE//$'\n'/ }" | ASK_SH_SHELL_PID=$$ ask-sh 2>/dev/null | head -n 1`
+    [ -z "$suggestion" ] && return
+    # the line is command<TAB>warning<TAB>explanation, see ask-sh pick
+    command="${suggestion%%$'\t'*}"
+    rest="${suggestion#*$'\t'}"
+    [ "$rest" != "$suggestion" ] && warning="${rest%%$'\t'*}"
+    warning="${warning% \[confirm\]}"
+    [ -n "$warning" ] && printf '%s\n' "$warning" >&2
+    READLINE_LINE="$command"
+    READLINE_POINT=${#READLINE_LINE}
+}
+if [ -n "$BASH_VERSION" ] && [[ $- == *i* ]] && [ -n "${ASK_SH_KEYBINDING-\C-x\C-a}" ]; then
+    bind -x "\"${ASK_SH_KEYBINDING-\C-x\C-a}\": _ask_sh_widget"
+fi
+"#;
+
+/// preexec/precmd hooks writing the last command to the file read through `ASK_SH_STATE_FILE`
+const ZSH_HOOKS: &str = r#"
+# Record the last command, its exit status, duration and directory for ask-sh
+ask_sh_state_file="${XDG_STATE_HOME:-$HOME/.local/state}/ask-sh/last-command-$$"
+_ask_sh_preexec() {
+    ask_sh_command="$1"
+    ask_sh_started=$SECONDS
+    ask_sh_cwd="$PWD"
+}
+_ask_sh_precmd() {
+    local exit_status=$?
+    [ -z "$ask_sh_command" ] && return $exit_status
+    case "$ask_sh_command" in
+        ask|ask\ *) ;; # ask itself is not the command to ask about
+        *)
+            if [ -z "$ASK_SH_NO_HISTORY" ] && mkdir -p "${ask_sh_state_file%/*}"; then
+                printf 'status=%s\nduration=%s\ncwd=%s\ncommand=%s\n' "$exit_status" "$((SECONDS - ask_sh_started))" "$ask_sh_cwd" "$ask_sh_command" > "$ask_sh_state_file"
+            fi
+            ;;
+    esac
+    ask_sh_command=""
+    # keep $? for the rest of PROMPT_COMMAND and the prompt
+    return $exit_status
+}
+_ask_sh_zshexit() {
+    rm -f "$ask_sh_state_file"
+}
+if [ -n "$ZSH_VERSION" ]; then
+    autoload -Uz add-zsh-hook
+    add-zsh-hook preexec _ask_sh_preexec
+    add-zsh-hook precmd _ask_sh_precmd
+    add-zsh-hook zshexit _ask_sh_zshexit
+fi
+"#;
+
+/// `PROMPT_COMMAND` and DEBUG trap equivalent of the zsh hooks
+const BASH_HOOKS: &str = r#"
+# Record the last command, its exit status, duration and directory for ask-sh
+ask_sh_state_file="${XDG_STATE_HOME:-$HOME/.local/state}/ask-sh/last-command-$$"
+# set by the first prompt, so the rest of .bashrc is not taken for a command
+ask_sh_at_prompt=""
+_ask_sh_preexec() {
+    # the DEBUG trap also runs for PROMPT_COMMAND, completion and key bindings
+    [ -n "$ask_sh_at_prompt" ] || return
+    [ -n "$COMP_LINE" ] || [ -n "${READLINE_POINT+set}" ] && return
+    ask_sh_at_prompt=""
+    local line number
+    line=`HISTTIMEFORMAT= builtin history 1`
+    [[ $line =~ ^\ *([0-9]+)\*?\ +(.*)$ ]] && number="${BASH_REMATCH[1]}"
+    # an empty line runs PROMPT_COMMAND without a command of the user
+    case "$PROMPT_COMMAND" in
+        "$BASH_COMMAND"*) return ;;
+    esac
+    # history has a new entry, unless it ignored the command (ignoredups, ignorespace)
+    if [ -n "$number" ] && [ "$number" != "$ask_sh_history_number" ]; then
+        ask_sh_command="${BASH_REMATCH[2]}"
+    else
+        ask_sh_command="$BASH_COMMAND"
+    fi
+    ask_sh_history_number="$number"
+    ask_sh_started=$SECONDS
+    ask_sh_cwd="$PWD"
+}
+_ask_sh_precmd() {
+    local exit_status=$?
+    ask_sh_at_prompt=1
+    if [ -z "${ask_sh_history_number+set}" ]; then
+        # the last entry of HISTFILE at the first prompt is not a command of this shell
+        local line
+        line=`HISTTIMEFORMAT= builtin history 1`
+        [[ $line =~ ^\ *([0-9]+) ]]
+        ask_sh_history_number="${BASH_REMATCH[1]}"
+    fi
+    [ -z "$ask_sh_command" ] && return $exit_status
+    case "$ask_sh_command" in
+        ask|ask\ *) ;; # ask itself is not the command to ask about
+        *)
+            if [ -z "$ASK_SH_NO_HISTORY" ] && mkdir -p "${ask_sh_state_file%/*}"; then
+                printf 'status=%s\nduration=%s\ncwd=%s\ncommand=%s\n' "$exit_status" "$((SECONDS - ask_sh_started))" "$ask_sh_cwd" "$ask_sh_command" > "$ask_sh_state_file"
+            fi
+            ;;
+    esac
+    ask_sh_command=""
+    # keep $? for the rest of PROMPT_COMMAND and the prompt
+    return $exit_status
+}
+# existing DEBUG and EXIT traps are left alone
+if [ -n "$BASH_VERSION" ] && [ -z "`trap -p DEBUG`" ]; then
+    PROMPT_COMMAND="_ask_sh_precmd${PROMPT_COMMAND:+; $PROMPT_COMMAND}"
+    trap '_ask_sh_preexec' DEBUG
+    [ -z "`trap -p EXIT`" ] && trap 'rm -f "$ask_sh_state_file"' EXIT
+fi
+"#;
+
+const FISH_SCRIPT: &str = r#"# This function is automatically generated by ask-sh --init fish
+# ask.sh fish function v2
+function ask --description 'Ask AI for commands that can be typed into your terminal'
+    set -l last_status $status # exit status of the command run before ask
+    if not command -q ask-sh
+        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
+        printf "👉 It's usually under ~/.cargo/bin/"
+        printf "👀 Please add it to your PATH and restart your shell."
+    end
+    set -l history_text
+    if not set -q ASK_SH_NO_HISTORY
+        set -l size 20
+        set -q ASK_SH_HISTORY_SIZE; and set size $ASK_SH_HISTORY_SIZE
+        # newest first, so reverse it
+        set -l entries (history --max $size)
+        set history_text (string join \n -- $entries[-1..1])
+    end
+    set -l suggested_commands (echo $argv | ASK_SH_SHELL_PID=$fish_pid ASK_SH_HISTORY=$history_text ASK_SH_LAST_STATUS=$last_status ASK_SH_STATE_FILE=$ask_sh_state_file ask-sh | string collect)
+    if test -n "$suggested_commands"
+        printf "\n" # add one empty line to create space
+        printf "👋 Hey, AI has suggested some commands that can be typed into your terminal.\n"
+        read -l -n 1 -P "🔍 Press Enter to view and select the commands, or type any other key to exit:" reply
+        if test -z (string trim -- "$reply")
+            # Enter moved the cursor to the next line; delete the 👋 and 🔍 lines and go back
+            printf "\033[2A\033[2K\n\033[2K\n\033[2A"
+            set -l selected_command (printf '%s\n' "$suggested_commands" | ask-sh pick | string collect)
+            if test -n "$selected_command"
+                # the command line is reset when the prompt is drawn, so fill it in from there
+                set -g __ask_sh_selected_command $selected_command
+                function __ask_sh_insert_command --on-event fish_prompt
+                    commandline -r -- $__ask_sh_selected_command
+                    set -e __ask_sh_selected_command
+                    functions -e __ask_sh_insert_command
+                end
+            end
+        else
+            printf "\033[1A\033[2K\n\033[2K\n\033[2A"
+        end
+    end
+    if not set -q ASK_SH_NO_UPDATE
+        set -l latest_version (cargo search ask-sh | string match -r '^ask-sh = "([^"]+)"')[2]
+        set -l current_version (ask-sh --version)
+        if test -n "$latest_version"; and test "$latest_version" != "$current_version"; and test (printf '%s\n' $latest_version $current_version | sort -rV | head -n1) = "$latest_version"
+            printf "\n"
+            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
+            read -l -n 1 -P "🆙 Press Enter to run update now, or type any other key to exit:" reply
+            if test -z (string trim -- "$reply")
+                cargo install --force ask-sh
+                printf "\nDone! Please restart your shell or source ~/.config/fish/config.fish to use the new version.\n"
+            else
+                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
+            end
+        end
+    end
+end
+"#;
+
+/// `fish_postexec` equivalent of the zsh hooks
+const FISH_HOOKS: &str = r#"
+# Record the last command, its exit status, duration and directory for ask-sh
+set -l ask_sh_state_home $HOME/.local/state
+set -q XDG_STATE_HOME; and set ask_sh_state_home $XDG_STATE_HOME
+set -g ask_sh_state_file $ask_sh_state_home/ask-sh/last-command-$fish_pid
+function __ask_sh_postexec --on-event fish_postexec
+    set -l exit_status $status
+    set -q ASK_SH_NO_HISTORY; and return
+    # ask itself is not the command to ask about
+    test -z "$argv[1]"; or string match -qr '^ask(\s|$)' -- $argv[1]; and return
+    mkdir -p (dirname $ask_sh_state_file); or return
+    printf 'status=%s\nduration=%s\ncwd=%s\ncommand=%s\n' $exit_status (math --scale=0 $CMD_DURATION / 1000) $PWD $argv[1] > $ask_sh_state_file
+end
+function __ask_sh_exit --on-event fish_exit
+    rm -f $ask_sh_state_file
+end
+"#;
+
+/// nushell is rarely the login shell in `$SHELL`, so the command sets `ASK_SH_SHELL` for the prompt
+const NU_SCRIPT: &str = r#"# This command is automatically generated by ask-sh --init nu
+# ask.sh nushell command v2
+def ask [...words: string] {
+    let last_status = ($env.LAST_EXIT_CODE? | default 0 | into string) # exit status of the command run before ask
+    if (which ask-sh | is-empty) {
+        print "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
+        print "👉 It's usually under ~/.cargo/bin/"
+        print "👀 Please add it to your PATH and restart your shell."
+        return
+    }
+    let history_text = if ($env.ASK_SH_NO_HISTORY? | is-empty) {
+        history | last ($env.ASK_SH_HISTORY_SIZE? | default 20 | into int) | get command | str join "\n"
+    } else {
+        ""
+    }
+    let suggested_commands = (with-env {ASK_SH_SHELL: "nu", ASK_SH_SHELL_PID: ($nu.pid | into string), ASK_SH_HISTORY: $history_text, ASK_SH_LAST_STATUS: $last_status} {
+        $words | str join " " | ^ask-sh
+    } | str trim)
+    if ($suggested_commands | is-not-empty) {
+        print "" # add one empty line to create space
+        print "👋 Hey, AI has suggested some commands that can be typed into your terminal."
+        let reply = (input --numchar 1 "🔍 Press Enter to view and select the commands, or type any other key to exit:")
+        if ($reply | str trim | is-empty) {
+            # Enter moved the cursor to the next line; delete the 👋 and 🔍 lines and go back
+            print -n "\e[2A\e[2K\n\e[2K\n\e[2A"
+            # ask-sh pick exits with 1 when nothing is chosen
+            let selected_command = (do -i { $suggested_commands | ^ask-sh pick } | str trim)
+            if ($selected_command | is-not-empty) {
+                commandline edit --replace $selected_command
+            }
+        } else {
+            print -n "\e[1A\e[2K\n\e[2K\n\e[2A"
+        }
+    }
+    if ($env.ASK_SH_NO_UPDATE? | is-empty) {
+        let found = (do -i { ^cargo search ask-sh } | lines | parse --regex '^ask-sh = "(?<version>[^"]+)"')
+        let current_version = (^ask-sh --version | str trim)
+        if ($found | is-not-empty) and ($found.0.version != $current_version) {
+            let latest_version = $found.0.version
+            let newest = ([$latest_version $current_version] | str join "\n" | ^sort -rV | lines | first)
+            if $newest == $latest_version {
+                print ""
+                print $"🎉 New version of ask-sh is available! \(Current: ($current_version) vs New: ($latest_version)\) Set $env.ASK_SH_NO_UPDATE = 1 to suppress this notice."
+                let reply = (input --numchar 1 "🆙 Press Enter to run update now, or type any other key to exit:")
+                if ($reply | str trim | is-empty) {
+                    ^cargo install --force ask-sh
+                    print "\nDone! Please restart your shell to use the new version."
+                } else {
+                    print "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'."
+                }
+            }
+        }
+    }
+}
+"#;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shell_from_path() {
+        assert_eq!(Shell::from_path("/usr/bin/fish"), Some(Shell::Fish));
+        assert_eq!(Shell::from_path("/bin/zsh"), Some(Shell::Zsh));
+        assert_eq!(Shell::from_path("bash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_path("/opt/homebrew/bin/nu"), Some(Shell::Nu));
+        assert_eq!(Shell::from_path("/bin/tcsh"), None);
+        assert!("powershell".parse::<Shell>().is_err());
+    }
+
+    #[test]
+    fn test_init_scripts() {
+        let zsh = Shell::Zsh.init_script();
+        assert!(zsh.contains("print -z"));
+        assert!(zsh.contains("ASK_SH_HISTORY=\"$ask_sh_history\""));
+        assert!(zsh.contains("zle -N ask-sh-widget"));
+        assert!(!zsh.contains("bind -x"));
+        assert!(zsh.contains("add-zsh-hook precmd _ask_sh_precmd"));
+        let bash = Shell::Bash.init_script();
+        assert!(bash.contains("READLINE_LINE"));
+        assert!(!bash.contains("bindkey"));
+        assert!(bash.contains("trap '_ask_sh_preexec' DEBUG"));
+        let fish = Shell::Fish.init_script();
+        assert!(
+            fish.starts_with("# This function is automatically generated by ask-sh --init fish")
+        );
+        assert!(fish.contains("commandline -r"));
+        assert!(fish.contains("ask-sh pick"));
+        assert!(!fish.contains("print -z"));
+        let nu = Shell::Nu.init_script();
+        assert!(nu.contains("commandline edit --replace"));
+        assert!(nu.contains("ASK_SH_SHELL: \"nu\""));
+    }
+
+    #[test]
+    fn test_bash_hooks_keep_the_exit_status() {
+        let dir = std::env::temp_dir().join(format!("ask-sh-init-{}", std::process::id()));
+        let script = format!(
+            r#"
+PROMPT_COMMAND='echo "prompt sees $?"'
+source /dev/stdin <<'ASK_SH_INIT'
+{}
+ASK_SH_INIT
+trap - DEBUG
+false; eval "$PROMPT_COMMAND"
+ask_sh_command="make"; ask_sh_started=$SECONDS; (exit 2); eval "$PROMPT_COMMAND"
+cat "$ask_sh_state_file"
+ask_sh_command="ask why"; (exit 3); eval "$PROMPT_COMMAND"
+"#,
+            Shell::Bash.init_script()
+        );
+        let output = match std::process::Command::new("bash")
+            .args(["-c", &script])
+            .env("XDG_STATE_HOME", &dir)
+            .output()
+        {
+            Ok(output) => output,
+            // bash is not installed
+            Err(_) => return,
+        };
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "prompt sees 1");
+        assert_eq!(lines[1], "prompt sees 2");
+        assert!(lines.contains(&"status=2"));
+        assert!(lines.contains(&"command=make"));
+        assert_eq!(lines.last(), Some(&"prompt sees 3"));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_bash_hooks_skip_empty_lines_and_the_first_prompt() {
+        let dir = std::env::temp_dir().join(format!("ask-sh-init-trap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // sourced like from .bashrc, with a stale entry in HISTFILE
+        std::fs::write(dir.join("bashrc"), Shell::Bash.init_script()).unwrap();
+        std::fs::write(dir.join("history"), "echo stale\n").unwrap();
+        let input = "\ncat \"$ask_sh_state_file\" || echo no state\nsleep 1\n\ncat \"$ask_sh_state_file\"\n";
+        let mut child = match std::process::Command::new("bash")
+            .args(["--rcfile", &dir.join("bashrc").to_string_lossy(), "-i"])
+            .env("XDG_STATE_HOME", &dir)
+            .env("HISTFILE", dir.join("history"))
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            // bash is not installed
+            Err(_) => return,
+        };
+        use std::io::Write;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(input.as_bytes())
+            .unwrap();
+        let output = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let lines: Vec<&str> = stdout.lines().collect();
+        assert_eq!(lines[0], "no state");
+        // the empty line after `sleep 1` keeps its duration
+        assert!(lines.contains(&"duration=1"), "{}", stdout);
+        assert!(lines.contains(&"command=sleep 1"), "{}", stdout);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
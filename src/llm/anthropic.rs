@@ -4,15 +4,21 @@ use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    retry_after, sse::sse_stream, ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider, Role,
+    StopReason, StreamError, StreamEvent, Usage, DEFAULT_MAX_TOKENS,
+};
 
-const ANTHROPIC_API_URL: &str = "https://api.anthropic.com/v1/messages";
+const ANTHROPIC_DEFAULT_URL: &str = "https://api.anthropic.com/v1";
 
 #[derive(Debug)]
 pub struct AnthropicProvider {
     client: Client,
     model: String,
     api_key: String,
+    base_url: String,
+    max_tokens: u32,
+    temperature: Option<f32>,
 }
 
 #[derive(Serialize, Debug)]
@@ -22,6 +28,8 @@ struct AnthropicRequest {
     messages: Vec<Message>,
     stream: bool,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
 }
 
 #[derive(Serialize, Debug)]
@@ -35,11 +43,42 @@ struct AnthropicStreamEvent {
     #[serde(rename = "type")]
     event_type: String,
     delta: Option<Delta>,
+    message: Option<MessageStart>,
+    usage: Option<AnthropicUsage>,
+    error: Option<AnthropicError>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Delta {
     text: Option<String>,
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct MessageStart {
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicUsage {
+    input_tokens: Option<u32>,
+    output_tokens: Option<u32>,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Usage {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct AnthropicError {
+    #[serde(rename = "type")]
+    error_type: String,
+    message: String,
 }
 
 impl AnthropicProvider {
@@ -48,45 +87,83 @@ impl AnthropicProvider {
             .build()
             .map_err(|e| LLMError::ConfigError(e.to_string()))?;
 
+        let base_url = config
+            .base_url
+            .unwrap_or_else(|| ANTHROPIC_DEFAULT_URL.to_string());
+
         Ok(Self {
             client,
             model: config.model,
             api_key: config.api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: config.temperature,
         })
     }
 
-    fn create_request(&self, system_message: &str, user_message: &str) -> AnthropicRequest {
+    fn create_request(&self, messages: &[ChatMessage]) -> AnthropicRequest {
+        // Anthropic takes the system prompt as a top-level field, not as a message
+        let system = messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n\n");
+
         AnthropicRequest {
             model: self.model.clone(),
-            system: system_message.to_string(),
-            messages: vec![Message {
-                role: "user".to_string(),
-                content: user_message.to_string(),
-            }],
+            system,
+            messages: messages
+                .iter()
+                .filter(|message| message.role != Role::System)
+                .map(|message| Message {
+                    role: message.role.as_str().to_string(),
+                    content: message.content.clone(),
+                })
+                .collect(),
             stream: true,
-            max_tokens: 4096,
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
         }
     }
 
-    fn parse_sse_line(line: &str) -> Option<String> {
-        if line.is_empty() || line.starts_with(':') {
-            return None;
-        }
+    fn parse_sse_data(data: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let event = match serde_json::from_str::<AnthropicStreamEvent>(data) {
+            Ok(event) => event,
+            Err(_) => return events,
+        };
 
-        if let Some(data) = line.strip_prefix("data: ") {
-            if data.trim() == "[DONE]" {
-                return None;
+        match event.event_type.as_str() {
+            "message_start" => {
+                if let Some(usage) = event.message.and_then(|message| message.usage) {
+                    events.push(StreamEvent::Usage(usage.into()));
+                }
             }
-
-            if let Ok(event) = serde_json::from_str::<AnthropicStreamEvent>(data) {
-                if event.event_type == "content_block_delta" {
-                    if let Some(delta) = event.delta {
-                        return delta.text;
-                    }
+            "content_block_delta" => {
+                if let Some(text) = event.delta.and_then(|delta| delta.text) {
+                    events.push(StreamEvent::Text(text));
+                }
+            }
+            "message_delta" => {
+                if let Some(reason) = event.delta.and_then(|delta| delta.stop_reason) {
+                    events.push(StreamEvent::Stop(StopReason::from_provider(&reason)));
+                }
+                if let Some(usage) = event.usage {
+                    events.push(StreamEvent::Usage(usage.into()));
                 }
             }
+            "error" => {
+                if let Some(error) = event.error {
+                    events.push(StreamEvent::Error(StreamError {
+                        kind: error.error_type,
+                        message: error.message,
+                    }));
+                }
+            }
+            _ => {}
         }
-        None
+        events
     }
 }
 
@@ -100,16 +177,12 @@ impl LLMProvider for AnthropicProvider {
         &self.model
     }
 
-    async fn chat_stream(
-        &self,
-        system_message: String,
-        user_message: String,
-    ) -> Result<ChatStream, LLMError> {
-        let request = self.create_request(&system_message, &user_message);
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let request = self.create_request(&messages);
 
         let response = self
             .client
-            .post(ANTHROPIC_API_URL)
+            .post(format!("{}/messages", self.base_url))
             .header(header::CONTENT_TYPE, "application/json")
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
@@ -119,50 +192,38 @@ impl LLMProvider for AnthropicProvider {
             .map_err(|e| LLMError::NetworkError(e.to_string()))?;
 
         if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(response.headers());
             let error_text = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(LLMError::ApiError(format!(
-                "Anthropic API error: {}",
-                error_text
-            )));
+            return Err(LLMError::HttpError {
+                status,
+                retry_after,
+                message: format!("Anthropic API error: {}", error_text),
+            });
         }
 
-        let stream = response.bytes_stream().map(move |result| match result {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                let mut content = String::new();
-
-                for line in text.lines() {
-                    if let Some(text) = Self::parse_sse_line(line) {
-                        content.push_str(&text);
-                    }
-                }
-
-                if !content.is_empty() {
-                    Ok(content)
-                } else {
-                    Ok(String::new())
-                }
-            }
-            Err(e) => Err(LLMError::NetworkError(e.to_string())),
+        let stream = sse_stream(response.bytes_stream()).flat_map(|result| {
+            let events = match result {
+                Ok(event) => Self::parse_sse_data(&event.data)
+                    .into_iter()
+                    .map(Ok)
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
         });
 
-        let filtered_stream = stream.filter(|result| {
-            futures::future::ready(match result {
-                Ok(content) => !content.is_empty(),
-                Err(_) => true,
-            })
-        });
-
-        Ok(Box::pin(filtered_stream))
+        Ok(Box::pin(stream))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::test_server::serve_once;
 
     #[tokio::test]
     async fn test_anthropic_provider_creation() {
@@ -171,10 +232,102 @@ mod tests {
             model: "claude-3-opus-20240229".to_string(),
             api_key: "test-key".to_string(),
             base_url: None,
+            ..Default::default()
         };
 
         let provider = AnthropicProvider::new(config).unwrap();
         assert_eq!(provider.name(), "anthropic");
         assert_eq!(provider.model(), "claude-3-opus-20240229");
     }
+
+    #[test]
+    fn test_anthropic_request_moves_system_out_of_messages() {
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-opus-20240229".to_string(),
+            api_key: "test-key".to_string(),
+            base_url: None,
+            ..Default::default()
+        };
+        let provider = AnthropicProvider::new(config).unwrap();
+
+        let request = provider.create_request(&[
+            ChatMessage::system("be brief"),
+            ChatMessage::user("first question"),
+            ChatMessage::assistant("first answer"),
+            ChatMessage::user("follow-up"),
+        ]);
+
+        assert_eq!(request.system, "be brief");
+        let roles: Vec<&str> = request.messages.iter().map(|m| m.role.as_str()).collect();
+        assert_eq!(roles, vec!["user", "assistant", "user"]);
+        assert_eq!(request.messages[1].content, "first answer");
+    }
+
+    #[test]
+    fn test_anthropic_parse_stream_events() {
+        let events = AnthropicProvider::parse_sse_data(
+            r#"{"type":"message_start","message":{"usage":{"input_tokens":25,"output_tokens":1}}}"#,
+        );
+        assert_eq!(
+            events,
+            vec![StreamEvent::Usage(Usage {
+                input_tokens: Some(25),
+                output_tokens: Some(1),
+            })]
+        );
+
+        let events = AnthropicProvider::parse_sse_data(
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#,
+        );
+        assert_eq!(events, vec![StreamEvent::Text("Hi".to_string())]);
+
+        let events = AnthropicProvider::parse_sse_data(
+            r#"{"type":"message_delta","delta":{"stop_reason":"max_tokens"},"usage":{"output_tokens":4096}}"#,
+        );
+        assert_eq!(events[0], StreamEvent::Stop(StopReason::MaxTokens));
+
+        let events = AnthropicProvider::parse_sse_data(
+            r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+        );
+        match &events[0] {
+            StreamEvent::Error(error) => assert!(error.is_overloaded()),
+            other => panic!("unexpected event: {:?}", other),
+        }
+
+        assert!(AnthropicProvider::parse_sse_data(r#"{"type":"ping"}"#).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_custom_base_url() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"pwd\"}}\n\n",
+            "event: message_stop\n",
+            "data: {\"type\":\"message_stop\"}\n\n"
+        );
+        let chunks = body.as_bytes().chunks(16).map(|c| c.to_vec()).collect();
+        let (base_url, recorded) = serve_once(200, "text/event-stream", chunks).await;
+
+        let config = LLMConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-opus-20240229".to_string(),
+            api_key: "test-key".to_string(),
+            base_url: Some(format!("{}/v1/", base_url)),
+            ..Default::default()
+        };
+        let provider = AnthropicProvider::new(config).unwrap();
+        let stream = provider
+            .chat_stream(vec![ChatMessage::user("where am I")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(events[1], StreamEvent::Text("pwd".to_string()));
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request.request_line.starts_with("POST /v1/messages "));
+        assert_eq!(request.header("x-api-key"), Some("test-key"));
+    }
 }
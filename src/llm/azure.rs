@@ -0,0 +1,149 @@
+use async_openai::config::AzureConfig;
+use async_trait::async_trait;
+use reqwest::Client;
+use std::fmt::Debug;
+
+use super::{openai::OpenAIProvider, ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider};
+
+/// api-version used when none is configured
+pub const AZURE_DEFAULT_API_VERSION: &str = "2024-02-01";
+
+/// First api-version accepting `stream_options`; older ones reject the request
+const AZURE_STREAM_USAGE_API_VERSION: &str = "2024-09-01";
+
+/// Azure OpenAI speaks the OpenAI chat API, but under
+/// `{endpoint}/openai/deployments/{deployment}/chat/completions?api-version=...`
+/// with an `api-key` header.
+#[derive(Debug)]
+pub struct AzureOpenAIProvider {
+    client: Client,
+    config: AzureConfig,
+    deployment: String,
+    include_usage: bool,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+}
+
+impl AzureOpenAIProvider {
+    /// `config.base_url` is the resource endpoint and `config.model` the deployment name
+    pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
+        let endpoint = config
+            .base_url
+            .ok_or_else(|| LLMError::ConfigError("Azure OpenAI endpoint not found".to_string()))?;
+        if config.model.is_empty() {
+            return Err(LLMError::ConfigError(
+                "Azure OpenAI deployment not found".to_string(),
+            ));
+        }
+
+        let api_version = config
+            .api_version
+            .unwrap_or_else(|| AZURE_DEFAULT_API_VERSION.to_string());
+        // dates, with an optional `-preview` suffix, compare as strings
+        let include_usage = api_version.as_str() >= AZURE_STREAM_USAGE_API_VERSION;
+        let azure_config = AzureConfig::new()
+            .with_api_base(endpoint.trim_end_matches('/'))
+            .with_deployment_id(&config.model)
+            .with_api_version(api_version)
+            .with_api_key(config.api_key);
+
+        Ok(Self {
+            client: Client::new(),
+            config: azure_config,
+            deployment: config.model,
+            include_usage,
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+        })
+    }
+}
+
+#[async_trait]
+impl LLMProvider for AzureOpenAIProvider {
+    fn name(&self) -> &'static str {
+        "azure"
+    }
+
+    fn model(&self) -> &str {
+        &self.deployment
+    }
+
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        // The deployment decides the model; the field is still required in the body
+        let request = OpenAIProvider::create_request(
+            &self.deployment,
+            self.max_tokens,
+            self.temperature,
+            &messages,
+        )?;
+
+        OpenAIProvider::send_stream(&self.client, &self.config, request, self.include_usage).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{test_server::serve_once, StopReason, StreamEvent};
+    use futures::stream::StreamExt;
+
+    fn config(base_url: Option<String>) -> LLMConfig {
+        LLMConfig {
+            provider: "azure".to_string(),
+            model: "gpt-4o-team".to_string(),
+            api_key: "test-key".to_string(),
+            base_url,
+            api_version: Some("2024-06-01".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_azure_provider_creation() {
+        let provider =
+            AzureOpenAIProvider::new(config(Some("https://res.openai.azure.com".to_string())))
+                .unwrap();
+        assert_eq!(provider.name(), "azure");
+        assert_eq!(provider.model(), "gpt-4o-team");
+
+        assert!(matches!(
+            AzureOpenAIProvider::new(config(None)),
+            Err(LLMError::ConfigError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_azure_uses_deployment_url_and_api_key_header() {
+        let body = concat!(
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"id\":\"1\",\"object\":\"chat.completion.chunk\",\"created\":1,\"model\":\"gpt-4o\",",
+            "\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n"
+        );
+        let (base_url, recorded) =
+            serve_once(200, "text/event-stream", vec![body.as_bytes().to_vec()]).await;
+
+        let provider = AzureOpenAIProvider::new(config(Some(base_url))).unwrap();
+        let stream = provider
+            .chat_stream(vec![ChatMessage::user("hello")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Text("hi".to_string()),
+                StreamEvent::Stop(StopReason::EndTurn),
+            ]
+        );
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request.request_line.starts_with(
+            "POST /openai/deployments/gpt-4o-team/chat/completions?api-version=2024-06-01 "
+        ));
+        assert_eq!(request.header("api-key"), Some("test-key"));
+        // 2024-06-01 predates `stream_options`
+        assert!(!request.body.contains("stream_options"));
+    }
+}
@@ -0,0 +1,409 @@
+use async_trait::async_trait;
+use futures::stream::StreamExt;
+use reqwest::{header, Client};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+
+use super::{
+    retry_after, sse::sse_stream, ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider, Role,
+    StopReason, StreamError, StreamEvent, Usage,
+};
+
+const GEMINI_DEFAULT_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+#[derive(Debug)]
+pub struct GeminiProvider {
+    client: Client,
+    model: String,
+    api_key: String,
+    base_url: String,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GeminiRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Content {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    role: Option<String>,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Part {
+    #[serde(default)]
+    text: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GeminiStreamEvent {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+    error: Option<GeminiError>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    content: Option<Content>,
+    finish_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize, Debug)]
+struct SafetyRating {
+    category: String,
+    probability: Option<String>,
+    #[serde(default)]
+    blocked: bool,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    prompt_token_count: Option<u32>,
+    candidates_token_count: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeminiError {
+    status: Option<String>,
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GeminiErrorResponse {
+    error: GeminiError,
+}
+
+impl GeminiProvider {
+    pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
+        let client = Client::builder()
+            .build()
+            .map_err(|e| LLMError::ConfigError(e.to_string()))?;
+
+        let base_url = config
+            .base_url
+            .unwrap_or_else(|| GEMINI_DEFAULT_URL.to_string());
+
+        Ok(Self {
+            client,
+            model: config.model,
+            api_key: config.api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+        })
+    }
+
+    fn create_request(&self, messages: &[ChatMessage]) -> GeminiRequest {
+        let system = messages
+            .iter()
+            .filter(|message| message.role == Role::System)
+            .map(|message| message.content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n\n");
+
+        let contents = messages
+            .iter()
+            .filter(|message| message.role != Role::System)
+            .map(|message| Content {
+                // Gemini calls the assistant "model"
+                role: Some(match message.role {
+                    Role::Assistant => "model".to_string(),
+                    _ => "user".to_string(),
+                }),
+                parts: vec![Part {
+                    text: message.content.clone(),
+                }],
+            })
+            .collect();
+
+        let generation_config = if self.max_tokens.is_some() || self.temperature.is_some() {
+            Some(GenerationConfig {
+                max_output_tokens: self.max_tokens,
+                temperature: self.temperature,
+            })
+        } else {
+            None
+        };
+
+        GeminiRequest {
+            system_instruction: if system.is_empty() {
+                None
+            } else {
+                Some(Content {
+                    role: None,
+                    parts: vec![Part { text: system }],
+                })
+            },
+            contents,
+            generation_config,
+        }
+    }
+
+    /// Categories that caused a block, e.g. "HARM_CATEGORY_DANGEROUS_CONTENT"
+    fn blocked_categories(ratings: &[SafetyRating]) -> String {
+        ratings
+            .iter()
+            .filter(|rating| {
+                rating.blocked || matches!(rating.probability.as_deref(), Some("HIGH"))
+            })
+            .map(|rating| rating.category.as_str())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+
+    fn parse_sse_data(data: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let event = match serde_json::from_str::<GeminiStreamEvent>(data) {
+            Ok(event) => event,
+            Err(_) => return events,
+        };
+
+        if let Some(error) = event.error {
+            events.push(StreamEvent::Error(StreamError {
+                kind: error.status.unwrap_or_else(|| "error".to_string()),
+                message: error.message,
+            }));
+            return events;
+        }
+
+        if let Some(feedback) = &event.prompt_feedback {
+            if let Some(reason) = &feedback.block_reason {
+                let categories = Self::blocked_categories(&feedback.safety_ratings);
+                events.push(StreamEvent::Error(StreamError {
+                    kind: "blocked".to_string(),
+                    message: format!("Gemini blocked the prompt ({}) {}", reason, categories)
+                        .trim()
+                        .to_string(),
+                }));
+                events.push(StreamEvent::Stop(StopReason::ContentFilter));
+            }
+        }
+
+        if let Some(candidate) = event.candidates.first() {
+            if let Some(content) = &candidate.content {
+                let text: String = content
+                    .parts
+                    .iter()
+                    .map(|part| part.text.as_str())
+                    .collect();
+                if !text.is_empty() {
+                    events.push(StreamEvent::Text(text));
+                }
+            }
+            if let Some(reason) = &candidate.finish_reason {
+                let stop_reason = match reason.as_str() {
+                    "STOP" => StopReason::EndTurn,
+                    "MAX_TOKENS" => StopReason::MaxTokens,
+                    "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" => {
+                        let categories = Self::blocked_categories(&candidate.safety_ratings);
+                        if !categories.is_empty() {
+                            events.push(StreamEvent::Error(StreamError {
+                                kind: "blocked".to_string(),
+                                message: format!("Gemini blocked the response: {}", categories),
+                            }));
+                        }
+                        StopReason::ContentFilter
+                    }
+                    other => StopReason::Other(other.to_string()),
+                };
+                events.push(StreamEvent::Stop(stop_reason));
+            }
+        }
+
+        if let Some(usage) = event.usage_metadata {
+            events.push(StreamEvent::Usage(Usage {
+                input_tokens: usage.prompt_token_count,
+                output_tokens: usage.candidates_token_count,
+            }));
+        }
+        events
+    }
+}
+
+#[async_trait]
+impl LLMProvider for GeminiProvider {
+    fn name(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let request = self.create_request(&messages);
+
+        let url = format!(
+            "{}/models/{}:streamGenerateContent?alt=sse",
+            self.base_url, self.model
+        );
+        let response = self
+            .client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-goog-api-key", &self.api_key)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| LLMError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(response.headers());
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            // Gemini returns a JSON array or object wrapping the error
+            let message = serde_json::from_str::<GeminiErrorResponse>(&error_text)
+                .ok()
+                .or_else(|| {
+                    serde_json::from_str::<Vec<GeminiErrorResponse>>(&error_text)
+                        .ok()
+                        .and_then(|mut errors| errors.pop())
+                })
+                .map(|response| response.error.message)
+                .unwrap_or(error_text);
+            return Err(LLMError::HttpError {
+                status,
+                retry_after,
+                message: format!("Gemini API error: {}", message),
+            });
+        }
+
+        let stream = sse_stream(response.bytes_stream()).flat_map(|result| {
+            let events = match result {
+                Ok(event) => Self::parse_sse_data(&event.data)
+                    .into_iter()
+                    .map(Ok)
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::test_server::serve_once;
+
+    fn config(base_url: Option<String>) -> LLMConfig {
+        LLMConfig {
+            provider: "gemini".to_string(),
+            model: "gemini-1.5-flash".to_string(),
+            api_key: "test-key".to_string(),
+            base_url,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gemini_provider_creation() {
+        let provider = GeminiProvider::new(config(None)).unwrap();
+        assert_eq!(provider.name(), "gemini");
+        assert_eq!(provider.model(), "gemini-1.5-flash");
+    }
+
+    #[tokio::test]
+    async fn test_gemini_streams_from_mock_server() {
+        let body = concat!(
+            "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"Run \"}],\"role\":\"model\"}}]}\r\n\r\n",
+            "data: {\"candidates\":[{\"content\":{\"parts\":[{\"text\":\"`ls`\"}],\"role\":\"model\"},\"finishReason\":\"STOP\"}],",
+            "\"usageMetadata\":{\"promptTokenCount\":9,\"candidatesTokenCount\":3}}\r\n\r\n"
+        );
+        let chunks = body.as_bytes().chunks(11).map(|c| c.to_vec()).collect();
+        let (base_url, recorded) = serve_once(200, "text/event-stream", chunks).await;
+
+        let provider = GeminiProvider::new(config(Some(format!("{}/v1beta", base_url)))).unwrap();
+        let stream = provider
+            .chat_stream(vec![
+                ChatMessage::system("be brief"),
+                ChatMessage::user("list files"),
+                ChatMessage::assistant("ls"),
+                ChatMessage::user("with hidden ones"),
+            ])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Text("Run ".to_string()),
+                StreamEvent::Text("`ls`".to_string()),
+                StreamEvent::Stop(StopReason::EndTurn),
+                StreamEvent::Usage(Usage {
+                    input_tokens: Some(9),
+                    output_tokens: Some(3),
+                }),
+            ]
+        );
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request
+            .request_line
+            .starts_with("POST /v1beta/models/gemini-1.5-flash:streamGenerateContent?alt=sse "));
+        assert_eq!(request.header("x-goog-api-key"), Some("test-key"));
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["systemInstruction"]["parts"][0]["text"], "be brief");
+        assert_eq!(body["contents"][1]["role"], "model");
+    }
+
+    #[test]
+    fn test_gemini_reports_safety_blocks() {
+        let events = GeminiProvider::parse_sse_data(
+            r#"{"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[{"category":"HARM_CATEGORY_DANGEROUS_CONTENT","probability":"HIGH"}]}}"#,
+        );
+        match &events[0] {
+            StreamEvent::Error(error) => {
+                assert!(error.message.contains("HARM_CATEGORY_DANGEROUS_CONTENT"))
+            }
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(events[1], StreamEvent::Stop(StopReason::ContentFilter));
+
+        let events = GeminiProvider::parse_sse_data(
+            r#"{"candidates":[{"finishReason":"SAFETY","safetyRatings":[{"category":"HARM_CATEGORY_HARASSMENT","probability":"MEDIUM","blocked":true}]}]}"#,
+        );
+        assert!(matches!(&events[0], StreamEvent::Error(e) if e.message.contains("HARASSMENT")));
+        assert_eq!(events[1], StreamEvent::Stop(StopReason::ContentFilter));
+    }
+}
@@ -0,0 +1,281 @@
+//! Offline provider replaying fixture files, and recording of real streams into them.
+//!
+//! A fixture is either plain text, streamed in small chunks and ended with `end_turn`,
+//! or a `.jsonl` file with one event per line:
+//!
+//! ```text
+//! {"text":"Use `ls -la`"}
+//! {"error":{"kind":"overloaded_error","message":"Overloaded"}}
+//! {"fail":"connection reset"}
+//! {"usage":{"input_tokens":12,"output_tokens":3}}
+//! {"stop":"end_turn"}
+//! ```
+//!
+//! `fail` injects a transport error into the stream, `error` an error reported by the provider.
+
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt::Debug,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider, StopReason, StreamError,
+    StreamEvent, Usage,
+};
+
+/// Size of the text chunks a plain text fixture is streamed in
+const CHUNK_CHARS: usize = 8;
+
+#[derive(Debug)]
+pub struct MockProvider {
+    fixture: PathBuf,
+    model: String,
+}
+
+/// One line of a `.jsonl` fixture
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum FixtureEvent {
+    Text(String),
+    Stop(String),
+    Usage {
+        input_tokens: Option<u32>,
+        output_tokens: Option<u32>,
+    },
+    Error {
+        kind: String,
+        message: String,
+    },
+    Fail(String),
+}
+
+impl FixtureEvent {
+    fn from_result(result: &Result<StreamEvent, LLMError>) -> Self {
+        match result {
+            Ok(StreamEvent::Text(text)) => FixtureEvent::Text(text.clone()),
+            Ok(StreamEvent::Stop(reason)) => FixtureEvent::Stop(reason.as_str().to_string()),
+            Ok(StreamEvent::Usage(usage)) => FixtureEvent::Usage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            },
+            Ok(StreamEvent::Error(error)) => FixtureEvent::Error {
+                kind: error.kind.clone(),
+                message: error.message.clone(),
+            },
+            Err(e) => FixtureEvent::Fail(e.to_string()),
+        }
+    }
+
+    fn into_result(self) -> Result<StreamEvent, LLMError> {
+        match self {
+            FixtureEvent::Text(text) => Ok(StreamEvent::Text(text)),
+            FixtureEvent::Stop(reason) => Ok(StreamEvent::Stop(StopReason::from_provider(&reason))),
+            FixtureEvent::Usage {
+                input_tokens,
+                output_tokens,
+            } => Ok(StreamEvent::Usage(Usage {
+                input_tokens,
+                output_tokens,
+            })),
+            FixtureEvent::Error { kind, message } => {
+                Ok(StreamEvent::Error(StreamError { kind, message }))
+            }
+            FixtureEvent::Fail(message) => Err(LLMError::NetworkError(message)),
+        }
+    }
+}
+
+impl MockProvider {
+    /// `config.base_url` is the path of the fixture file
+    pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
+        let fixture = config
+            .base_url
+            .ok_or_else(|| LLMError::ConfigError("Mock fixture not found".to_string()))?;
+        let model = if config.model.is_empty() {
+            "mock".to_string()
+        } else {
+            config.model
+        };
+
+        Ok(Self {
+            fixture: PathBuf::from(fixture),
+            model,
+        })
+    }
+
+    fn load_events(&self) -> Result<Vec<FixtureEvent>, LLMError> {
+        let text = fs::read_to_string(&self.fixture)
+            .map_err(|e| LLMError::ConfigError(format!("{}: {}", self.fixture.display(), e)))?;
+
+        if self.fixture.extension().is_some_and(|ext| ext == "jsonl") {
+            return text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| !line.trim().is_empty())
+                .map(|(i, line)| {
+                    serde_json::from_str(line).map_err(|e| {
+                        LLMError::ConfigError(format!(
+                            "{}:{}: {}",
+                            self.fixture.display(),
+                            i + 1,
+                            e
+                        ))
+                    })
+                })
+                .collect();
+        }
+
+        let chars: Vec<char> = text.chars().collect();
+        let mut events: Vec<FixtureEvent> = chars
+            .chunks(CHUNK_CHARS)
+            .map(|chunk| FixtureEvent::Text(chunk.iter().collect()))
+            .collect();
+        events.push(FixtureEvent::Stop("end_turn".to_string()));
+        Ok(events)
+    }
+}
+
+#[async_trait]
+impl LLMProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat_stream(&self, _messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let events = self.load_events()?;
+        Ok(Box::pin(stream::iter(
+            events.into_iter().map(FixtureEvent::into_result),
+        )))
+    }
+}
+
+/// Pass `stream` through unchanged while writing every event to a `.jsonl` fixture at `path`
+pub fn record(stream: ChatStream, path: &Path) -> Result<ChatStream, LLMError> {
+    let mut file = File::create(path)
+        .map_err(|e| LLMError::ConfigError(format!("{}: {}", path.display(), e)))?;
+
+    let stream = stream.map(move |result| {
+        if let Ok(line) = serde_json::to_string(&FixtureEvent::from_result(&result)) {
+            // a broken recording must not break the answer
+            let _ = writeln!(file, "{}", line);
+        }
+        result
+    });
+    Ok(Box::pin(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    fn fixture(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ask-sh-mock-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn provider(path: &Path) -> MockProvider {
+        MockProvider::new(LLMConfig {
+            provider: "mock".to_string(),
+            base_url: Some(path.to_string_lossy().into_owned()),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_mock_streams_plain_text_in_chunks() {
+        let path = fixture("plain.txt", "Run ```ls -la``` to list files");
+        let stream = provider(&path).chat_stream(vec![]).await.unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+
+        assert_eq!(events[0], StreamEvent::Text("Run ```l".to_string()));
+        assert_eq!(events.last(), Some(&StreamEvent::Stop(StopReason::EndTurn)));
+        let text: String = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::Text(text) => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "Run ```ls -la``` to list files");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mock_replays_jsonl_with_injected_errors() {
+        let path = fixture(
+            "events.jsonl",
+            concat!(
+                "{\"text\":\"partial\"}\n",
+                "{\"error\":{\"kind\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+                "{\"fail\":\"connection reset\"}\n",
+                "{\"stop\":\"max_tokens\"}\n"
+            ),
+        );
+        let stream = provider(&path).chat_stream(vec![]).await.unwrap();
+        let events: Vec<Result<StreamEvent, LLMError>> = stream.collect().await;
+
+        assert_eq!(
+            events[0].as_ref().unwrap(),
+            &StreamEvent::Text("partial".to_string())
+        );
+        assert!(matches!(&events[1], Ok(StreamEvent::Error(error)) if error.is_overloaded()));
+        assert!(matches!(&events[2], Err(LLMError::NetworkError(_))));
+        assert_eq!(
+            events[3].as_ref().unwrap(),
+            &StreamEvent::Stop(StopReason::MaxTokens)
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_then_replay() {
+        let source = fixture(
+            "source.jsonl",
+            concat!(
+                "{\"text\":\"pwd\"}\n",
+                "{\"usage\":{\"input_tokens\":5,\"output_tokens\":1}}\n",
+                "{\"stop\":\"end_turn\"}\n"
+            ),
+        );
+        let recording = env::temp_dir().join(format!(
+            "ask-sh-mock-{}-recording.jsonl",
+            std::process::id()
+        ));
+
+        let stream = provider(&source).chat_stream(vec![]).await.unwrap();
+        let recorded: Vec<StreamEvent> = record(stream, &recording)
+            .unwrap()
+            .map(|event| event.unwrap())
+            .collect()
+            .await;
+
+        let replay = provider(&recording).chat_stream(vec![]).await.unwrap();
+        let replayed: Vec<StreamEvent> = replay.map(|event| event.unwrap()).collect().await;
+        assert_eq!(recorded, replayed);
+        assert_eq!(replayed.len(), 3);
+
+        fs::remove_file(source).unwrap();
+        fs::remove_file(recording).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_mock_missing_fixture() {
+        let provider = provider(Path::new("/nonexistent/ask-sh-fixture.txt"));
+        assert!(matches!(
+            provider.chat_stream(vec![]).await,
+            Err(LLMError::ConfigError(_))
+        ));
+    }
+}
@@ -1,14 +1,18 @@
 use async_trait::async_trait;
 use futures::Stream;
-use std::{fmt::Debug, pin::Pin};
+use reqwest::header::HeaderMap;
+use serde::Serialize;
+use std::{
+    fmt::Debug,
+    pin::Pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use thiserror::Error;
 
 /// Error from LLM provider
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum LLMError {
-    #[error("API error: {0}")]
-    ApiError(String),
-
     #[error("Configuration error: {0}")]
     ConfigError(String),
 
@@ -17,30 +21,257 @@ pub enum LLMError {
 
     #[error("Invalid request: {0}")]
     InvalidRequestError(String),
+
+    /// The provider answered with an error status
+    #[error("{message}")]
+    HttpError {
+        status: u16,
+        /// From the `Retry-After` header
+        retry_after: Option<Duration>,
+        message: String,
+    },
+
+    /// The provider reported an error in the stream before the answer started
+    #[error("{}: {}", .0.kind, .0.message)]
+    StreamError(StreamError),
+}
+
+impl LLMError {
+    /// Whether the same request may succeed later: rate limits, overloads, server errors and
+    /// dropped connections
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LLMError::HttpError { status, .. } => {
+                matches!(status, 408 | 409 | 429) || *status >= 500
+            }
+            LLMError::NetworkError(_) => true,
+            LLMError::StreamError(error) => error.is_retryable(),
+            _ => false,
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LLMError::HttpError { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// The delay asked for by `Retry-After` (or OpenAI's `retry-after-ms`), in seconds or as an
+/// HTTP date
+pub fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| Some(headers.get(name)?.to_str().ok()?.trim());
+    let seconds = |name: &str| header(name)?.parse::<f64>().ok();
+    seconds("retry-after-ms")
+        .map(|millis| millis / 1000.0)
+        .or_else(|| seconds("retry-after"))
+        .filter(|seconds| seconds.is_finite() && *seconds >= 0.0)
+        .map(Duration::from_secs_f64)
+        .or_else(|| {
+            let date = parse_http_date(header("retry-after")?)?;
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+            Some(Duration::from_secs(date.saturating_sub(now)))
+        })
+}
+
+/// Seconds since the epoch of an HTTP date in the form servers send,
+/// e.g. `Sun, 06 Nov 1994 08:49:37 GMT`
+fn parse_http_date(text: &str) -> Option<u64> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let (_, date) = text.split_once(", ")?;
+    let [day, month, year, time, "GMT"] = date.split(' ').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let day: i64 = day.parse().ok()?;
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+    let year: i64 = year.parse().ok()?;
+    let time = time
+        .split(':')
+        .map(|part| part.parse::<i64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [hour, minute, second] = time[..] else {
+        return None;
+    };
+
+    // civil date to days, from Howard Hinnant's date algorithms
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    u64::try_from(days * 86_400 + hour * 3600 + minute * 60 + second).ok()
 }
 
 /// LLM configuration
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct LLMConfig {
     pub provider: String,
     pub model: String,
     pub api_key: String,
-    pub base_url: Option<String>, // Custom endpoint URL (for OpenAI)
+    /// Custom endpoint URL, up to and including the version path (e.g. `.../v1`): providers only
+    /// append the endpoint such as `/chat/completions`. Azure takes the resource endpoint
+    /// instead, and the mock provider the path of its fixture.
+    pub base_url: Option<String>,
+    pub max_tokens: Option<u32>,
+    pub temperature: Option<f32>,
+    pub num_ctx: Option<u32>,        // Context window size (for Ollama)
+    pub keep_alive: Option<String>,  // How long the model stays loaded (for Ollama)
+    pub api_version: Option<String>, // api-version query parameter (for Azure OpenAI)
+    pub stream_usage: Option<bool>,  // Ask for the token usage in the stream (for OpenAI)
+}
+
+/// Output token limit used when none is configured
+pub const DEFAULT_MAX_TOKENS: u32 = 4096;
+
+/// Role of the author of a chat message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+impl Role {
+    /// Returns the role name used by the chat completion APIs
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+        }
+    }
+}
+
+/// A single turn of a conversation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
 }
 
-impl Default for LLMConfig {
-    fn default() -> Self {
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
         Self {
-            provider: String::new(),
-            model: String::new(),
-            api_key: String::new(),
-            base_url: None,
+            role: Role::User,
+            content: content.into(),
         }
     }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// Why the model stopped generating
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// The model finished its answer
+    EndTurn,
+    /// The answer was cut off by the output token limit
+    MaxTokens,
+    /// A stop sequence was generated
+    StopSequence,
+    /// The provider's content filter stopped the answer
+    ContentFilter,
+    /// Any other reason reported by the provider
+    Other(String),
+}
+
+impl StopReason {
+    /// Parse the stop/finish reason strings used by the providers
+    pub fn from_provider(reason: &str) -> Self {
+        match reason {
+            "end_turn" | "stop" => StopReason::EndTurn,
+            "max_tokens" | "length" => StopReason::MaxTokens,
+            "stop_sequence" => StopReason::StopSequence,
+            "content_filter" => StopReason::ContentFilter,
+            other => StopReason::Other(other.to_string()),
+        }
+    }
+
+    /// Name of the reason, as understood by `from_provider`
+    pub fn as_str(&self) -> &str {
+        match self {
+            StopReason::EndTurn => "end_turn",
+            StopReason::MaxTokens => "max_tokens",
+            StopReason::StopSequence => "stop_sequence",
+            StopReason::ContentFilter => "content_filter",
+            StopReason::Other(other) => other,
+        }
+    }
+}
+
+/// Token usage reported by the provider.
+/// Fields are `None` until the provider reports them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub input_tokens: Option<u32>,
+    pub output_tokens: Option<u32>,
+}
+
+impl Usage {
+    /// Overwrite the fields reported in `other`
+    pub fn merge(&mut self, other: Usage) {
+        if other.input_tokens.is_some() {
+            self.input_tokens = other.input_tokens;
+        }
+        if other.output_tokens.is_some() {
+            self.output_tokens = other.output_tokens;
+        }
+    }
+}
+
+/// Error reported by the provider in the middle of a stream
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StreamError {
+    /// Provider specific error type, e.g. `overloaded_error`
+    pub kind: String,
+    pub message: String,
+}
+
+impl StreamError {
+    pub fn is_overloaded(&self) -> bool {
+        self.kind == "overloaded_error"
+    }
+
+    /// Overloads, rate limits and internal errors of the provider
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind.as_str(),
+            "overloaded_error" | "rate_limit_error" | "api_error" | "server_error"
+        )
+    }
+}
+
+/// Event emitted by a chat stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    /// A piece of the answer
+    Text(String),
+    /// The model stopped generating
+    Stop(StopReason),
+    /// Token usage so far
+    Usage(Usage),
+    /// The provider reported an error
+    Error(StreamError),
 }
 
 /// Type alias for chat stream
-pub type ChatStream = Pin<Box<dyn Stream<Item = Result<String, LLMError>> + Send + 'static>>;
+pub type ChatStream = Pin<Box<dyn Stream<Item = Result<StreamEvent, LLMError>> + Send + 'static>>;
 
 /// Trait for LLM provider
 #[async_trait]
@@ -51,17 +282,22 @@ pub trait LLMProvider: Send + Sync + Debug {
     /// Returns the current model name
     fn model(&self) -> &str;
 
-    /// Get chat completion as a stream
-    async fn chat_stream(
-        &self,
-        system_message: String,
-        user_message: String,
-    ) -> Result<ChatStream, LLMError>;
+    /// Get chat completion as a stream.
+    /// `messages` is the whole conversation in order, oldest first.
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError>;
 }
 
 pub mod anthropic;
-pub mod openai;
+pub mod azure;
+pub mod gemini;
+pub mod mock;
 pub mod nanogpt;
+pub mod ollama;
+pub mod openai;
+pub mod retry;
+pub mod sse;
+#[cfg(test)]
+pub mod test_server;
 
 /// Available LLM providers
 #[derive(Debug)]
@@ -69,6 +305,10 @@ pub enum Provider {
     OpenAI(openai::OpenAIProvider),
     Anthropic(anthropic::AnthropicProvider),
     NanoGPT(nanogpt::NanoGPTProvider),
+    Ollama(ollama::OllamaProvider),
+    Gemini(gemini::GeminiProvider),
+    Azure(azure::AzureOpenAIProvider),
+    Mock(mock::MockProvider),
 }
 
 #[async_trait]
@@ -78,6 +318,10 @@ impl LLMProvider for Provider {
             Provider::OpenAI(p) => p.name(),
             Provider::Anthropic(p) => p.name(),
             Provider::NanoGPT(p) => p.name(),
+            Provider::Ollama(p) => p.name(),
+            Provider::Gemini(p) => p.name(),
+            Provider::Azure(p) => p.name(),
+            Provider::Mock(p) => p.name(),
         }
     }
 
@@ -86,18 +330,22 @@ impl LLMProvider for Provider {
             Provider::OpenAI(p) => p.model(),
             Provider::Anthropic(p) => p.model(),
             Provider::NanoGPT(p) => p.model(),
+            Provider::Ollama(p) => p.model(),
+            Provider::Gemini(p) => p.model(),
+            Provider::Azure(p) => p.model(),
+            Provider::Mock(p) => p.model(),
         }
     }
 
-    async fn chat_stream(
-        &self,
-        system_message: String,
-        user_message: String,
-    ) -> Result<ChatStream, LLMError> {
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
         match self {
-            Provider::OpenAI(p) => p.chat_stream(system_message, user_message).await,
-            Provider::Anthropic(p) => p.chat_stream(system_message, user_message).await,
-            Provider::NanoGPT(p) => p.chat_stream(system_message, user_message).await,
+            Provider::OpenAI(p) => p.chat_stream(messages).await,
+            Provider::Anthropic(p) => p.chat_stream(messages).await,
+            Provider::NanoGPT(p) => p.chat_stream(messages).await,
+            Provider::Ollama(p) => p.chat_stream(messages).await,
+            Provider::Gemini(p) => p.chat_stream(messages).await,
+            Provider::Azure(p) => p.chat_stream(messages).await,
+            Provider::Mock(p) => p.chat_stream(messages).await,
         }
     }
 }
@@ -110,6 +358,10 @@ pub fn create_provider(config: LLMConfig) -> Result<Provider, LLMError> {
             config,
         )?)),
         "nanogpt" => Ok(Provider::NanoGPT(nanogpt::NanoGPTProvider::new(config)?)),
+        "ollama" => Ok(Provider::Ollama(ollama::OllamaProvider::new(config)?)),
+        "gemini" => Ok(Provider::Gemini(gemini::GeminiProvider::new(config)?)),
+        "azure" => Ok(Provider::Azure(azure::AzureOpenAIProvider::new(config)?)),
+        "mock" => Ok(Provider::Mock(mock::MockProvider::new(config)?)),
         _ => Err(LLMError::ConfigError(format!(
             "Unknown provider: {}",
             config.provider
@@ -4,15 +4,21 @@ use reqwest::{header, Client};
 use serde::{Deserialize, Serialize};
 use std::fmt::Debug;
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    retry_after, sse::sse_stream, ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider,
+    StopReason, StreamError, StreamEvent, Usage, DEFAULT_MAX_TOKENS,
+};
 
-const NANOGPT_API_URL: &str = "https://nano-gpt.com/api/v1/chat/completions";
+const NANOGPT_DEFAULT_URL: &str = "https://nano-gpt.com/api/v1";
 
 #[derive(Debug)]
 pub struct NanoGPTProvider {
     client: Client,
     model: String,
     api_key: String,
+    base_url: String,
+    max_tokens: u32,
+    temperature: Option<f32>,
 }
 
 #[derive(Serialize, Debug)]
@@ -21,6 +27,8 @@ struct NanoGPTRequest {
     messages: Vec<Message>,
     stream: bool,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
 }
 
 #[derive(Serialize, Debug)]
@@ -31,14 +39,30 @@ struct Message {
 
 #[derive(Deserialize, Debug)]
 struct NanoGPTStreamEvent {
-    object: String,
+    object: Option<String>,
+    #[serde(default)]
     choices: Vec<Choice>,
+    usage: Option<NanoGPTUsage>,
+    error: Option<NanoGPTError>,
 }
 
 #[derive(Deserialize, Debug)]
 struct Choice {
     delta: Option<Delta>,
-    // finish_reason: Option<String>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NanoGPTUsage {
+    prompt_tokens: Option<u32>,
+    completion_tokens: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct NanoGPTError {
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+    message: String,
 }
 
 #[derive(Deserialize, Debug)]
@@ -52,49 +76,73 @@ impl NanoGPTProvider {
             .build()
             .map_err(|e| LLMError::ConfigError(e.to_string()))?;
 
+        let base_url = config
+            .base_url
+            .unwrap_or_else(|| NANOGPT_DEFAULT_URL.to_string());
+
         Ok(Self {
             client,
             model: config.model,
             api_key: config.api_key,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_tokens: config.max_tokens.unwrap_or(DEFAULT_MAX_TOKENS),
+            temperature: config.temperature,
         })
     }
 
-    fn create_request(&self, system_message: &str, user_message: &str) -> NanoGPTRequest {
+    fn create_request(&self, messages: &[ChatMessage]) -> NanoGPTRequest {
         NanoGPTRequest {
             model: self.model.clone(),
-            messages: vec![
-            Message {
-                role: "system".to_string(),
-                content: system_message.to_string(),
-            },
-            Message {
-                role: "user".to_string(),
-                content: user_message.to_string(),
-            }],
+            messages: messages
+                .iter()
+                .map(|message| Message {
+                    role: message.role.as_str().to_string(),
+                    content: message.content.clone(),
+                })
+                .collect(),
             stream: true,
-            max_tokens: 4096,
+            max_tokens: self.max_tokens,
+            temperature: self.temperature,
         }
     }
 
-    fn parse_sse_line(line: &str) -> Option<String> {
-        if line.is_empty() || line.starts_with(':') {
-            return None;
+    fn parse_sse_data(data: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let event = match serde_json::from_str::<NanoGPTStreamEvent>(data) {
+            Ok(event) => event,
+            Err(_) => return events,
+        };
+
+        if let Some(error) = event.error {
+            events.push(StreamEvent::Error(StreamError {
+                kind: error.error_type.unwrap_or_else(|| "error".to_string()),
+                message: error.message,
+            }));
+            return events;
+        }
+        if event.object.as_deref() != Some("chat.completion.chunk") {
+            return events;
         }
-        
-        if let Some(data) = line.strip_prefix("data: ") {
-            let event = serde_json::from_str::<NanoGPTStreamEvent>(data).ok()?;
-            if event.object != "chat.completion.chunk" {
-                return None;
+
+        if let Some(choice) = event.choices.first() {
+            if let Some(content) = choice
+                .delta
+                .as_ref()
+                .and_then(|delta| delta.content.clone())
+            {
+                events.push(StreamEvent::Text(content));
             }
-        
-            let choice = event.choices.get(0)?;
-            if let Some(delta) = &choice.delta {
-                if let Some(content) = &delta.content {
-                    return Some(content.clone());
-                }
+            if let Some(reason) = &choice.finish_reason {
+                events.push(StreamEvent::Stop(StopReason::from_provider(reason)));
             }
         }
-        None
+        if let Some(usage) = event.usage {
+            events.push(StreamEvent::Usage(Usage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+            }));
+        }
+        events
     }
 }
 
@@ -108,16 +156,12 @@ impl LLMProvider for NanoGPTProvider {
         &self.model
     }
 
-    async fn chat_stream(
-        &self,
-        system_message: String,
-        user_message: String,
-    ) -> Result<ChatStream, LLMError> {
-        let request = self.create_request(&system_message, &user_message);
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let request = self.create_request(&messages);
 
         let response = self
             .client
-            .post(NANOGPT_API_URL)
+            .post(format!("{}/chat/completions", self.base_url))
             .header(header::CONTENT_TYPE, "application/json")
             .header("authorization", format!("Bearer {}", &self.api_key))
             .header("accept", "text/event-stream")
@@ -127,50 +171,38 @@ impl LLMProvider for NanoGPTProvider {
             .map_err(|e| LLMError::NetworkError(e.to_string()))?;
 
         if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let retry_after = retry_after(response.headers());
             let error_text = response
                 .text()
                 .await
                 .unwrap_or_else(|_| "Unknown error".to_string());
-            return Err(LLMError::ApiError(format!(
-                "NanoGPT API error: {}",
-                error_text
-            )));
+            return Err(LLMError::HttpError {
+                status,
+                retry_after,
+                message: format!("NanoGPT API error: {}", error_text),
+            });
         }
 
-        let stream = response.bytes_stream().map(move |result| match result {
-            Ok(bytes) => {
-                let text = String::from_utf8_lossy(&bytes);
-                let mut content = String::new();
-
-                for line in text.lines() {
-                    if let Some(text) = Self::parse_sse_line(line) {
-                        content.push_str(&text);
-                    }
-                }
-
-                if !content.is_empty() {
-                    Ok(content)
-                } else {
-                    Ok(String::new())
-                }
-            }
-            Err(e) => Err(LLMError::NetworkError(e.to_string())),
-        });
-
-        let filtered_stream = stream.filter(|result| {
-            futures::future::ready(match result {
-                Ok(content) => !content.is_empty(),
-                Err(_) => true,
-            })
+        let stream = sse_stream(response.bytes_stream()).flat_map(|result| {
+            let events = match result {
+                Ok(event) => Self::parse_sse_data(&event.data)
+                    .into_iter()
+                    .map(Ok)
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
         });
 
-        Ok(Box::pin(filtered_stream))
+        Ok(Box::pin(stream))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::test_server::serve_once;
 
     #[tokio::test]
     async fn test_nanogpt_provider_creation() {
@@ -179,10 +211,70 @@ mod tests {
             model: "gpt-4o".to_string(),
             api_key: "test-key".to_string(),
             base_url: None,
+            ..Default::default()
         };
 
         let provider = NanoGPTProvider::new(config).unwrap();
         assert_eq!(provider.name(), "nanogpt");
         assert_eq!(provider.model(), "gpt-4o");
     }
+
+    #[test]
+    fn test_nanogpt_parse_stream_events() {
+        let events = NanoGPTProvider::parse_sse_data(
+            r#"{"object":"chat.completion.chunk","choices":[{"delta":{"content":"ls"},"finish_reason":null}]}"#,
+        );
+        assert_eq!(events, vec![StreamEvent::Text("ls".to_string())]);
+
+        let events = NanoGPTProvider::parse_sse_data(
+            r#"{"object":"chat.completion.chunk","choices":[{"delta":{},"finish_reason":"length"}],"usage":{"prompt_tokens":10,"completion_tokens":4096}}"#,
+        );
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Stop(StopReason::MaxTokens),
+                StreamEvent::Usage(Usage {
+                    input_tokens: Some(10),
+                    output_tokens: Some(4096),
+                }),
+            ]
+        );
+
+        let events =
+            NanoGPTProvider::parse_sse_data(r#"{"error":{"message":"insufficient balance"}}"#);
+        assert!(matches!(&events[0], StreamEvent::Error(e) if e.message == "insufficient balance"));
+
+        assert!(NanoGPTProvider::parse_sse_data("[DONE]").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nanogpt_custom_base_url() {
+        let body = concat!(
+            "data: {\"object\":\"chat.completion.chunk\",\"choices\":[{\"delta\":{\"content\":\"whoami\"}}]}\n\n",
+            "data: [DONE]\n\n"
+        );
+        let chunks = body.as_bytes().chunks(9).map(|c| c.to_vec()).collect();
+        let (base_url, recorded) = serve_once(200, "text/event-stream", chunks).await;
+
+        let config = LLMConfig {
+            provider: "nanogpt".to_string(),
+            model: "gpt-4o".to_string(),
+            api_key: "test-key".to_string(),
+            base_url: Some(format!("{}/api/v1", base_url)),
+            ..Default::default()
+        };
+        let provider = NanoGPTProvider::new(config).unwrap();
+        let stream = provider
+            .chat_stream(vec![ChatMessage::user("who am I")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(events, vec![StreamEvent::Text("whoami".to_string())]);
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request
+            .request_line
+            .starts_with("POST /api/v1/chat/completions "));
+        assert_eq!(request.header("authorization"), Some("Bearer test-key"));
+    }
 }
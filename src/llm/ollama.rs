@@ -0,0 +1,360 @@
+use async_trait::async_trait;
+use futures::stream::{self, Stream, StreamExt};
+use reqwest::{header, Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt::Debug, pin::Pin};
+
+use super::{
+    ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider, StopReason, StreamError,
+    StreamEvent, Usage,
+};
+
+const OLLAMA_DEFAULT_URL: &str = "http://localhost:11434/api";
+
+#[derive(Debug)]
+pub struct OllamaProvider {
+    client: Client,
+    model: String,
+    base_url: String,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    num_ctx: Option<u32>,
+    keep_alive: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct OllamaRequest {
+    model: String,
+    messages: Vec<Message>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<Options>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Debug)]
+struct Message {
+    role: String,
+    content: String,
+}
+
+#[derive(Serialize, Debug)]
+struct Options {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_ctx: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+/// One line of the NDJSON response of /api/chat
+#[derive(Deserialize, Debug)]
+struct OllamaChunk {
+    message: Option<ChunkMessage>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkMessage {
+    content: String,
+}
+
+impl OllamaProvider {
+    pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
+        let client = Client::builder()
+            .build()
+            .map_err(|e| LLMError::ConfigError(e.to_string()))?;
+
+        let base_url = config
+            .base_url
+            .unwrap_or_else(|| OLLAMA_DEFAULT_URL.to_string());
+
+        Ok(Self {
+            client,
+            model: config.model,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            num_ctx: config.num_ctx,
+            keep_alive: config.keep_alive,
+        })
+    }
+
+    fn create_request(&self, messages: &[ChatMessage]) -> OllamaRequest {
+        let options =
+            if self.num_ctx.is_some() || self.max_tokens.is_some() || self.temperature.is_some() {
+                Some(Options {
+                    num_ctx: self.num_ctx,
+                    num_predict: self.max_tokens,
+                    temperature: self.temperature,
+                })
+            } else {
+                None
+            };
+
+        // keep_alive is either a duration ("10m") or a number of seconds (-1 keeps it loaded)
+        let keep_alive = self.keep_alive.as_ref().map(|value| {
+            value
+                .parse::<i64>()
+                .map(serde_json::Value::from)
+                .unwrap_or_else(|_| serde_json::Value::from(value.as_str()))
+        });
+
+        OllamaRequest {
+            model: self.model.clone(),
+            messages: messages
+                .iter()
+                .map(|message| Message {
+                    role: message.role.as_str().to_string(),
+                    content: message.content.clone(),
+                })
+                .collect(),
+            stream: true,
+            options,
+            keep_alive,
+        }
+    }
+
+    fn parse_line(line: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let chunk = match serde_json::from_str::<OllamaChunk>(line) {
+            Ok(chunk) => chunk,
+            Err(_) => return events,
+        };
+
+        if let Some(error) = chunk.error {
+            events.push(StreamEvent::Error(StreamError {
+                kind: "error".to_string(),
+                message: error,
+            }));
+            return events;
+        }
+        if let Some(message) = chunk.message {
+            if !message.content.is_empty() {
+                events.push(StreamEvent::Text(message.content));
+            }
+        }
+        if chunk.done {
+            let reason = chunk.done_reason.as_deref().unwrap_or("stop");
+            events.push(StreamEvent::Stop(StopReason::from_provider(reason)));
+            events.push(StreamEvent::Usage(Usage {
+                input_tokens: chunk.prompt_eval_count,
+                output_tokens: chunk.eval_count,
+            }));
+        }
+        events
+    }
+
+    /// Turn the error body of a failed request into a helpful message
+    fn describe_error(&self, status: StatusCode, body: &str) -> LLMError {
+        let message = serde_json::from_str::<OllamaChunk>(body)
+            .ok()
+            .and_then(|chunk| chunk.error)
+            .unwrap_or_else(|| body.to_string());
+        if status == StatusCode::NOT_FOUND && message.contains("not found") {
+            return LLMError::ConfigError(format!(
+                "Ollama model {} is not pulled. Run `ollama pull {}` first.",
+                self.model, self.model
+            ));
+        }
+        LLMError::HttpError {
+            status: status.as_u16(),
+            retry_after: None,
+            message: format!("Ollama API error: {}", message),
+        }
+    }
+}
+
+/// Split a byte stream into lines, keeping partial lines until they are complete
+fn ndjson_lines<S, B, E>(bytes: S) -> impl Stream<Item = Result<String, LLMError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: std::fmt::Display,
+{
+    struct State<S> {
+        inner: Pin<Box<S>>,
+        buffer: Vec<u8>,
+        pending: VecDeque<Result<String, LLMError>>,
+        done: bool,
+    }
+
+    let state = State {
+        inner: Box::pin(bytes),
+        buffer: Vec::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.pending.pop_front() {
+                return Some((item, state));
+            }
+            if state.done {
+                return None;
+            }
+            match state.inner.next().await {
+                Some(Ok(bytes)) => {
+                    state.buffer.extend_from_slice(bytes.as_ref());
+                    while let Some(pos) = state.buffer.iter().position(|b| *b == b'\n') {
+                        let line: Vec<u8> = state.buffer.drain(..=pos).collect();
+                        let line = String::from_utf8_lossy(&line).trim().to_string();
+                        if !line.is_empty() {
+                            state.pending.push_back(Ok(line));
+                        }
+                    }
+                }
+                Some(Err(e)) => {
+                    state
+                        .pending
+                        .push_back(Err(LLMError::NetworkError(e.to_string())));
+                }
+                None => {
+                    state.done = true;
+                    let line = String::from_utf8_lossy(&state.buffer).trim().to_string();
+                    state.buffer.clear();
+                    if !line.is_empty() {
+                        state.pending.push_back(Ok(line));
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[async_trait]
+impl LLMProvider for OllamaProvider {
+    fn name(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let request = self.create_request(&messages);
+
+        let url = format!("{}/chat", self.base_url);
+        let response = self
+            .client
+            .post(&url)
+            .header(header::CONTENT_TYPE, "application/json")
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| {
+                LLMError::NetworkError(format!(
+                    "Could not reach Ollama at {} (is `ollama serve` running?): {}",
+                    self.base_url, e
+                ))
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(self.describe_error(status, &error_text));
+        }
+
+        let stream = ndjson_lines(response.bytes_stream()).flat_map(|result| {
+            let events = match result {
+                Ok(line) => Self::parse_line(&line).into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
+        });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::test_server::serve_once;
+
+    fn config(base_url: Option<String>) -> LLMConfig {
+        LLMConfig {
+            provider: "ollama".to_string(),
+            model: "llama3.2".to_string(),
+            base_url,
+            num_ctx: Some(8192),
+            keep_alive: Some("-1".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ollama_provider_creation() {
+        let provider = OllamaProvider::new(config(None)).unwrap();
+        assert_eq!(provider.name(), "ollama");
+        assert_eq!(provider.model(), "llama3.2");
+        assert_eq!(provider.base_url, OLLAMA_DEFAULT_URL);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_streams_ndjson_split_across_chunks() {
+        let body = concat!(
+            r#"{"message":{"role":"assistant","content":"Use "},"done":false}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":"`ls`"},"done":false}"#,
+            "\n",
+            r#"{"message":{"role":"assistant","content":""},"done":true,"done_reason":"length","prompt_eval_count":12,"eval_count":3}"#,
+            "\n"
+        );
+        let chunks = body.as_bytes().chunks(7).map(|c| c.to_vec()).collect();
+        let (base_url, recorded) = serve_once(200, "application/x-ndjson", chunks).await;
+
+        let provider = OllamaProvider::new(config(Some(format!("{}/api", base_url)))).unwrap();
+        let stream = provider
+            .chat_stream(vec![ChatMessage::user("list files")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Text("Use ".to_string()),
+                StreamEvent::Text("`ls`".to_string()),
+                StreamEvent::Stop(StopReason::MaxTokens),
+                StreamEvent::Usage(Usage {
+                    input_tokens: Some(12),
+                    output_tokens: Some(3),
+                }),
+            ]
+        );
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request.request_line.starts_with("POST /api/chat "));
+        assert_eq!(request.header("content-type"), Some("application/json"));
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["options"]["num_ctx"], 8192);
+        assert_eq!(body["keep_alive"], -1);
+        assert_eq!(body["messages"][0]["role"], "user");
+    }
+
+    #[tokio::test]
+    async fn test_ollama_reports_model_not_pulled() {
+        let body = br#"{"error":"model \"llama3.2\" not found, try pulling it first"}"#.to_vec();
+        let (base_url, _) = serve_once(404, "application/json", vec![body]).await;
+
+        let provider = OllamaProvider::new(config(Some(format!("{}/api", base_url)))).unwrap();
+        match provider.chat_stream(vec![ChatMessage::user("hi")]).await {
+            Err(LLMError::ConfigError(message)) => assert!(message.contains("ollama pull")),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("expected an error"),
+        }
+    }
+}
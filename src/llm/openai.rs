@@ -1,39 +1,235 @@
 use async_openai::{
-    config::OpenAIConfig,
+    config::{Config, OpenAIConfig},
     types::{
+        ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestMessage,
         ChatCompletionRequestSystemMessageArgs, ChatCompletionRequestUserMessageArgs,
-        CreateChatCompletionRequestArgs,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs,
     },
-    Client,
 };
 use async_trait::async_trait;
 use futures::stream::StreamExt;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
 use std::fmt::Debug;
 
-use super::{ChatStream, LLMConfig, LLMError, LLMProvider};
+use super::{
+    retry_after, sse::sse_stream, ChatMessage, ChatStream, LLMConfig, LLMError, LLMProvider, Role,
+    StopReason, StreamError, StreamEvent, Usage,
+};
+
+/// The endpoint used when no `base_url` is set
+const OPENAI_API_BASE: &str = "https://api.openai.com/v1";
+
+/// The request is built with async-openai, but the stream is read here: its chunk type has no
+/// `usage` field.
+#[derive(Deserialize, Debug)]
+struct OpenAIStreamChunk {
+    #[serde(default)]
+    choices: Vec<Choice>,
+    usage: Option<OpenAIUsage>,
+    error: Option<OpenAIError>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Choice {
+    delta: Option<Delta>,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Delta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIUsage {
+    prompt_tokens: Option<u32>,
+    completion_tokens: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OpenAIError {
+    #[serde(rename = "type")]
+    error_type: Option<String>,
+    message: String,
+}
 
 #[derive(Debug)]
 pub struct OpenAIProvider {
-    client: Client<OpenAIConfig>,
+    client: Client,
+    config: OpenAIConfig,
     model: String,
+    max_tokens: Option<u32>,
+    temperature: Option<f32>,
+    include_usage: bool,
 }
 
 impl OpenAIProvider {
     pub fn new(config: LLMConfig) -> Result<Self, LLMError> {
         let mut openai_config = OpenAIConfig::new().with_api_key(config.api_key);
+        // compatible servers like LM Studio or llama.cpp may reject `stream_options`
+        let include_usage = config.stream_usage.unwrap_or_else(|| {
+            config
+                .base_url
+                .as_deref()
+                .is_none_or(|base_url| base_url.trim_end_matches('/') == OPENAI_API_BASE)
+        });
 
         // Set custom base_url if specified
         if let Some(base_url) = config.base_url {
             openai_config = openai_config.with_api_base(&base_url);
         }
 
-        let client = Client::with_config(openai_config);
-
         Ok(Self {
-            client,
+            client: Client::new(),
+            config: openai_config,
             model: config.model,
+            max_tokens: config.max_tokens,
+            temperature: config.temperature,
+            include_usage,
         })
     }
+
+    fn to_request_message(message: &ChatMessage) -> Result<ChatCompletionRequestMessage, LLMError> {
+        let content = message.content.as_str();
+        let request_message = match message.role {
+            Role::System => ChatCompletionRequestSystemMessageArgs::default()
+                .content(content)
+                .build()
+                .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
+                .into(),
+            Role::User => ChatCompletionRequestUserMessageArgs::default()
+                .content(content)
+                .build()
+                .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
+                .into(),
+            Role::Assistant => ChatCompletionRequestAssistantMessageArgs::default()
+                .content(content)
+                .build()
+                .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
+                .into(),
+        };
+        Ok(request_message)
+    }
+
+    /// Build a chat completion request; shared with the Azure OpenAI provider
+    pub(super) fn create_request(
+        model: &str,
+        max_tokens: Option<u32>,
+        temperature: Option<f32>,
+        messages: &[ChatMessage],
+    ) -> Result<CreateChatCompletionRequest, LLMError> {
+        let request_messages = messages
+            .iter()
+            .map(Self::to_request_message)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut request_args = CreateChatCompletionRequestArgs::default();
+        request_args.model(model).messages(request_messages);
+        if let Some(max_tokens) = max_tokens {
+            request_args.max_tokens(u16::try_from(max_tokens).unwrap_or(u16::MAX));
+        }
+        if let Some(temperature) = temperature {
+            request_args.temperature(temperature);
+        }
+        request_args
+            .build()
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))
+    }
+
+    /// Send a chat completion request and stream the answer; shared with the Azure OpenAI
+    /// provider. `include_usage` asks for the token usage in a last chunk, which older
+    /// Azure api-versions and some OpenAI-compatible servers reject.
+    pub(super) async fn send_stream<C: Config>(
+        client: &Client,
+        config: &C,
+        request: CreateChatCompletionRequest,
+        include_usage: bool,
+    ) -> Result<ChatStream, LLMError> {
+        let mut body = serde_json::to_value(request)
+            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
+        body["stream"] = json!(true);
+        if include_usage {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+
+        let response = client
+            .post(config.url("/chat/completions"))
+            .query(&config.query())
+            .headers(config.headers())
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| LLMError::NetworkError(e.to_string()))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let retry_after = retry_after(response.headers());
+            let error_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "Unknown error".to_string());
+            return Err(LLMError::HttpError {
+                status: status.as_u16(),
+                retry_after,
+                message: format!("OpenAI API error ({}): {}", status, error_text),
+            });
+        }
+
+        let stream = sse_stream(response.bytes_stream()).flat_map(|result| {
+            let events = match result {
+                Ok(event) => Self::parse_sse_data(&event.data)
+                    .into_iter()
+                    .map(Ok)
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            };
+            futures::stream::iter(events)
+        });
+
+        Ok(Box::pin(stream))
+    }
+
+    fn parse_sse_data(data: &str) -> Vec<StreamEvent> {
+        let mut events = Vec::new();
+        let chunk = match serde_json::from_str::<OpenAIStreamChunk>(data) {
+            Ok(chunk) => chunk,
+            // `[DONE]` and keep-alives
+            Err(_) => return events,
+        };
+
+        if let Some(error) = chunk.error {
+            events.push(StreamEvent::Error(StreamError {
+                kind: error.error_type.unwrap_or_else(|| "error".to_string()),
+                message: error.message,
+            }));
+            return events;
+        }
+        let content: String = chunk
+            .choices
+            .iter()
+            .filter_map(|choice| choice.delta.as_ref()?.content.as_deref())
+            .collect();
+        if !content.is_empty() {
+            events.push(StreamEvent::Text(content));
+        }
+        if let Some(reason) = chunk
+            .choices
+            .iter()
+            .find_map(|choice| choice.finish_reason.as_deref())
+        {
+            events.push(StreamEvent::Stop(StopReason::from_provider(reason)));
+        }
+        // the last chunk, with no choices, when `include_usage` is set
+        if let Some(usage) = chunk.usage {
+            events.push(StreamEvent::Usage(Usage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+            }));
+        }
+        events
+    }
 }
 
 #[async_trait]
@@ -46,58 +242,18 @@ impl LLMProvider for OpenAIProvider {
         &self.model
     }
 
-    async fn chat_stream(
-        &self,
-        system_message: String,
-        user_message: String,
-    ) -> Result<ChatStream, LLMError> {
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.model)
-            .messages([
-                ChatCompletionRequestSystemMessageArgs::default()
-                    .content(system_message.as_str())
-                    .build()
-                    .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
-                    .into(),
-                ChatCompletionRequestUserMessageArgs::default()
-                    .content(user_message.as_str())
-                    .build()
-                    .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?
-                    .into(),
-            ])
-            .build()
-            .map_err(|e| LLMError::InvalidRequestError(e.to_string()))?;
-
-        let stream = self
-            .client
-            .chat()
-            .create_stream(request)
-            .await
-            .map_err(|e| LLMError::ApiError(e.to_string()))?;
-
-        // Convert OpenAI stream to a stream using LLMError
-        let mapped_stream = stream.map(|result| match result {
-            Ok(response) => {
-                let content = response
-                    .choices
-                    .iter()
-                    .filter_map(|choice| choice.delta.content.as_ref())
-                    .fold(String::new(), |mut acc, s| {
-                        acc.push_str(s);
-                        acc
-                    });
-                Ok(content)
-            }
-            Err(err) => Err(LLMError::ApiError(err.to_string())),
-        });
+    async fn chat_stream(&self, messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+        let request =
+            Self::create_request(&self.model, self.max_tokens, self.temperature, &messages)?;
 
-        Ok(Box::pin(mapped_stream))
+        Self::send_stream(&self.client, &self.config, request, self.include_usage).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::llm::test_server::serve_once;
 
     #[tokio::test]
     async fn test_openai_provider_creation() {
@@ -106,10 +262,144 @@ mod tests {
             model: "gpt-3.5-turbo".to_string(),
             api_key: "test-key".to_string(),
             base_url: None,
+            ..Default::default()
         };
 
         let provider = OpenAIProvider::new(config).unwrap();
         assert_eq!(provider.name(), "openai");
         assert_eq!(provider.model(), "gpt-3.5-turbo");
     }
+
+    fn provider(base_url: String, stream_usage: Option<bool>) -> OpenAIProvider {
+        OpenAIProvider::new(LLMConfig {
+            provider: "openai".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            api_key: "test-key".to_string(),
+            base_url: Some(base_url),
+            stream_usage,
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_openai_stream_reports_usage() {
+        let body = concat!(
+            "data: {\"object\":\"chat.completion.chunk\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"hi\"},\"finish_reason\":null}]}\n\n",
+            "data: {\"object\":\"chat.completion.chunk\",",
+            "\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"length\"}]}\n\n",
+            "data: {\"object\":\"chat.completion.chunk\",\"choices\":[],",
+            "\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":3,\"total_tokens\":15}}\n\n",
+            "data: [DONE]\n\n"
+        );
+        let (base_url, recorded) =
+            serve_once(200, "text/event-stream", vec![body.as_bytes().to_vec()]).await;
+
+        let stream = provider(base_url, Some(true))
+            .chat_stream(vec![ChatMessage::user("hello")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Text("hi".to_string()),
+                StreamEvent::Stop(StopReason::MaxTokens),
+                StreamEvent::Usage(Usage {
+                    input_tokens: Some(12),
+                    output_tokens: Some(3),
+                }),
+            ]
+        );
+
+        let request = recorded.lock().unwrap().clone();
+        assert!(request.request_line.starts_with("POST /chat/completions "));
+        assert_eq!(request.header("authorization"), Some("Bearer test-key"));
+        let body: serde_json::Value = serde_json::from_str(&request.body).unwrap();
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["stream_options"]["include_usage"], true);
+    }
+
+    #[tokio::test]
+    async fn test_usage_is_only_asked_from_openai() {
+        let config = |base_url: Option<&str>| LLMConfig {
+            base_url: base_url.map(str::to_string),
+            ..Default::default()
+        };
+        assert!(OpenAIProvider::new(config(None)).unwrap().include_usage);
+        assert!(
+            OpenAIProvider::new(config(Some("https://api.openai.com/v1/")))
+                .unwrap()
+                .include_usage
+        );
+        assert!(
+            !OpenAIProvider::new(config(Some("http://localhost:1234/v1")))
+                .unwrap()
+                .include_usage
+        );
+
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"hi\"}}]}\n\ndata: [DONE]\n\n";
+        let (base_url, recorded) =
+            serve_once(200, "text/event-stream", vec![body.as_bytes().to_vec()]).await;
+        let stream = provider(base_url, None)
+            .chat_stream(vec![ChatMessage::user("hello")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(events, vec![StreamEvent::Text("hi".to_string())]);
+        let body: serde_json::Value = serde_json::from_str(&recorded.lock().unwrap().body).unwrap();
+        assert!(body.get("stream_options").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_openai_http_error() {
+        let body = r#"{"error":{"message":"Unsupported parameter: stream_options","type":"invalid_request_error"}}"#;
+        let (base_url, _) =
+            serve_once(400, "application/json", vec![body.as_bytes().to_vec()]).await;
+        let error = match provider(base_url, Some(true))
+            .chat_stream(vec![ChatMessage::user("hello")])
+            .await
+        {
+            Err(error) => error,
+            Ok(_) => panic!("a 400 response must fail the request"),
+        };
+        match error {
+            LLMError::HttpError {
+                status,
+                retry_after,
+                message,
+            } => {
+                assert_eq!(status, 400);
+                assert_eq!(retry_after, None);
+                assert!(message.contains("Unsupported parameter: stream_options"));
+            }
+            other => panic!("unexpected error: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_openai_error_chunk() {
+        let chunks = vec![
+            b"data: {\"choices\":[{\"delta\":{\"content\":\"par\"}}]}\n\n".to_vec(),
+            b"data: {\"error\":{\"type\":\"server_error\",\"message\":\"The server had an error\"}}\n\n"
+                .to_vec(),
+        ];
+        let (base_url, _) = serve_once(200, "text/event-stream", chunks).await;
+        let stream = provider(base_url, None)
+            .chat_stream(vec![ChatMessage::user("hello")])
+            .await
+            .unwrap();
+        let events: Vec<StreamEvent> = stream.map(|event| event.unwrap()).collect().await;
+        assert_eq!(
+            events,
+            vec![
+                StreamEvent::Text("par".to_string()),
+                StreamEvent::Error(StreamError {
+                    kind: "server_error".to_string(),
+                    message: "The server had an error".to_string(),
+                }),
+            ]
+        );
+    }
 }
@@ -0,0 +1,288 @@
+//! Retries of failed requests with exponential backoff.
+//! A request is only retried before the first token of the answer, so nothing shown to the
+//! user is ever repeated.
+
+use futures::stream::{self, StreamExt};
+use std::time::Duration;
+
+use super::{ChatMessage, ChatStream, LLMError, LLMProvider, StreamEvent};
+
+/// Retries used when none are configured
+pub const DEFAULT_MAX_RETRIES: u32 = 2;
+
+/// How often and how long to wait before giving up on a provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled for each further one
+    pub base_delay: Duration,
+    /// Longest delay waited for. A provider asking for more with `Retry-After` is given up on,
+    /// so the next one in the fallback chain is tried instead.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: DEFAULT_MAX_RETRIES,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry` (from 0), or `None` to give up
+    pub fn delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if retry >= self.max_retries {
+            return None;
+        }
+        match retry_after {
+            Some(delay) if delay > self.max_delay => None,
+            Some(delay) => Some(delay),
+            None => Some(
+                self.base_delay
+                    .saturating_mul(2u32.saturating_pow(retry))
+                    .min(self.max_delay),
+            ),
+        }
+    }
+}
+
+/// Open a chat stream, retrying retryable failures until the first token arrives.
+/// `on_retry` is told about each failure and the delay before the next attempt.
+pub async fn chat_stream<P: LLMProvider + ?Sized>(
+    provider: &P,
+    messages: &[ChatMessage],
+    policy: &RetryPolicy,
+    mut on_retry: impl FnMut(&LLMError, Duration),
+) -> Result<ChatStream, LLMError> {
+    let mut retry = 0;
+    loop {
+        let result = match provider.chat_stream(messages.to_vec()).await {
+            Ok(stream) => wait_for_first_token(stream).await,
+            Err(error) => Err(error),
+        };
+        let error = match result {
+            Ok(stream) => return Ok(stream),
+            Err(error) if error.is_retryable() => error,
+            Err(error) => return Err(error),
+        };
+        match policy.delay(retry, error.retry_after()) {
+            Some(delay) => {
+                on_retry(&error, delay);
+                tokio::time::sleep(delay).await;
+                retry += 1;
+            }
+            None => return Err(error),
+        }
+    }
+}
+
+/// Read `stream` up to the first piece of the answer and put the events back in front.
+/// A retryable error before it fails the attempt.
+async fn wait_for_first_token(mut stream: ChatStream) -> Result<ChatStream, LLMError> {
+    let mut buffered = Vec::new();
+    while let Some(result) = stream.next().await {
+        match result {
+            Ok(StreamEvent::Error(error)) if error.is_retryable() => {
+                return Err(LLMError::StreamError(error));
+            }
+            Err(error) if error.is_retryable() => return Err(error),
+            Ok(StreamEvent::Text(text)) => {
+                buffered.push(Ok(StreamEvent::Text(text)));
+                break;
+            }
+            other => buffered.push(other),
+        }
+    }
+    Ok(Box::pin(stream::iter(buffered).chain(stream)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::{StopReason, StreamError};
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    /// The events of a stream, or the error opening it
+    type Outcome = Result<Vec<Result<StreamEvent, LLMError>>, LLMError>;
+
+    /// Answers each request with the next outcome
+    #[derive(Debug)]
+    struct FlakyProvider {
+        outcomes: Mutex<Vec<Outcome>>,
+    }
+
+    #[async_trait]
+    impl LLMProvider for FlakyProvider {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+
+        fn model(&self) -> &str {
+            "flaky-1"
+        }
+
+        async fn chat_stream(&self, _messages: Vec<ChatMessage>) -> Result<ChatStream, LLMError> {
+            let events = self.outcomes.lock().unwrap().remove(0)?;
+            Ok(Box::pin(stream::iter(events)))
+        }
+    }
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(50),
+        }
+    }
+
+    fn overloaded() -> StreamEvent {
+        StreamEvent::Error(StreamError {
+            kind: "overloaded_error".to_string(),
+            message: "Overloaded".to_string(),
+        })
+    }
+
+    fn rate_limited(retry_after: Option<Duration>) -> LLMError {
+        LLMError::HttpError {
+            status: 429,
+            retry_after,
+            message: "rate limited".to_string(),
+        }
+    }
+
+    async fn collect(stream: ChatStream) -> Vec<StreamEvent> {
+        stream.map(|event| event.unwrap()).collect().await
+    }
+
+    #[test]
+    fn test_delays() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay(0, None), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(1, None), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay(2, None), None);
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(7))),
+            Some(Duration::from_secs(7))
+        );
+        // asks for too long: give up and fall back
+        assert_eq!(policy.delay(0, Some(Duration::from_secs(120))), None);
+        let many = RetryPolicy {
+            max_retries: 10,
+            ..policy
+        };
+        assert_eq!(many.delay(9, None), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        use crate::llm::retry_after;
+        use reqwest::header::{HeaderMap, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        assert_eq!(retry_after(&headers), None);
+        headers.insert("retry-after", HeaderValue::from_static("3"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+        headers.insert("retry-after-ms", HeaderValue::from_static("250"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_millis(250)));
+        headers.clear();
+        // a date in the past asks for no delay
+        headers.insert(
+            "retry-after",
+            HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"),
+        );
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+        headers.insert(
+            "retry-after",
+            HeaderValue::from_static("Fri, 31 Dec 9999 23:59:59 GMT"),
+        );
+        assert!(retry_after(&headers).unwrap() > Duration::from_secs(86_400 * 365));
+        headers.insert("retry-after", HeaderValue::from_static("soon"));
+        assert_eq!(retry_after(&headers), None);
+        assert_eq!(
+            crate::llm::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+        assert_eq!(
+            crate::llm::parse_http_date("Tue, 29 Feb 2028 00:00:00 GMT"),
+            Some(1_835_395_200)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_before_the_first_token() {
+        let provider = FlakyProvider {
+            outcomes: Mutex::new(vec![
+                Err(rate_limited(Some(Duration::from_millis(2)))),
+                Ok(vec![Ok(overloaded())]),
+                Ok(vec![
+                    Ok(StreamEvent::Text("hi".to_string())),
+                    Ok(StreamEvent::Stop(StopReason::EndTurn)),
+                ]),
+            ]),
+        };
+        let mut delays = Vec::new();
+        let stream = chat_stream(&provider, &[], &policy(2), |_, delay| delays.push(delay))
+            .await
+            .unwrap();
+        assert_eq!(
+            delays,
+            vec![Duration::from_millis(2), Duration::from_millis(2)]
+        );
+        assert_eq!(
+            collect(stream).await,
+            vec![
+                StreamEvent::Text("hi".to_string()),
+                StreamEvent::Stop(StopReason::EndTurn),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_after_the_first_token_or_for_client_errors() {
+        // the error comes after the answer started: passed through, not retried
+        let provider = FlakyProvider {
+            outcomes: Mutex::new(vec![Ok(vec![
+                Ok(StreamEvent::Text("hi".to_string())),
+                Ok(overloaded()),
+            ])]),
+        };
+        let stream = chat_stream(&provider, &[], &policy(2), |_, _| panic!("retried"))
+            .await
+            .unwrap();
+        assert_eq!(
+            collect(stream).await,
+            vec![StreamEvent::Text("hi".to_string()), overloaded()]
+        );
+
+        let provider = FlakyProvider {
+            outcomes: Mutex::new(vec![Err(LLMError::HttpError {
+                status: 401,
+                retry_after: None,
+                message: "invalid x-api-key".to_string(),
+            })]),
+        };
+        let result = chat_stream(&provider, &[], &policy(2), |_, _| panic!("retried")).await;
+        assert!(matches!(
+            result,
+            Err(LLMError::HttpError { status: 401, .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let provider = FlakyProvider {
+            outcomes: Mutex::new(vec![
+                Err(LLMError::NetworkError("reset".to_string())),
+                Err(LLMError::NetworkError("reset".to_string())),
+            ]),
+        };
+        let mut retries = 0;
+        let result = chat_stream(&provider, &[], &policy(1), |_, _| retries += 1).await;
+        assert_eq!(retries, 1);
+        assert!(matches!(result, Err(LLMError::NetworkError(_))));
+    }
+}
@@ -0,0 +1,277 @@
+use futures::stream::{self, Stream, StreamExt};
+use std::{collections::VecDeque, fmt::Display, pin::Pin};
+
+use super::LLMError;
+
+/// A single Server-Sent Event
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SseEvent {
+    /// Value of the `event:` field, if any
+    pub event: Option<String>,
+    /// Value of the `data:` fields, joined with newlines
+    pub data: String,
+    /// Value of the `id:` field, if any
+    pub id: Option<String>,
+}
+
+/// Incremental Server-Sent Events decoder.
+/// Bytes can be fed in arbitrary chunks: partial lines (including split
+/// multi-byte characters and `\r\n` pairs) are kept until they are complete.
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of bytes and return the events completed by it
+    pub fn push(&mut self, bytes: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(line) = self.next_line(false) {
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush whatever is left at the end of the stream.
+    /// Servers often omit the final blank line, so a pending event is dispatched.
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let mut events = Vec::new();
+        while let Some(line) = self.next_line(true) {
+            if let Some(event) = self.process_line(&line) {
+                events.push(event);
+            }
+        }
+        if let Some(event) = self.process_line("") {
+            events.push(event);
+        }
+        events
+    }
+
+    /// Take the next complete line out of the buffer, without its terminator
+    fn next_line(&mut self, at_eof: bool) -> Option<String> {
+        let pos = self.buffer.iter().position(|b| *b == b'\n' || *b == b'\r');
+        let (end, terminator_len) = match pos {
+            Some(pos) if self.buffer[pos] == b'\r' => match self.buffer.get(pos + 1) {
+                Some(b'\n') => (pos, 2),
+                Some(_) => (pos, 1),
+                // A lone `\r` at the end may be the first half of `\r\n`
+                None if at_eof => (pos, 1),
+                None => return None,
+            },
+            Some(pos) => (pos, 1),
+            None if at_eof && !self.buffer.is_empty() => (self.buffer.len(), 0),
+            None => return None,
+        };
+        let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+        self.buffer.drain(..end + terminator_len);
+        Some(line)
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            // comment, used as keep-alive
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => match &mut self.data {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            },
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            // `retry` and unknown fields are ignored
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let data = self.data.take()?;
+        if data.is_empty() {
+            // an empty data buffer dispatches nothing
+            return None;
+        }
+        Some(SseEvent {
+            event,
+            data,
+            id: self.id.clone(),
+        })
+    }
+}
+
+/// Decode a stream of byte chunks (e.g. `reqwest::Response::bytes_stream`) into events
+pub fn sse_stream<S, B, E>(bytes: S) -> impl Stream<Item = Result<SseEvent, LLMError>>
+where
+    S: Stream<Item = Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Display,
+{
+    struct State<S> {
+        inner: Pin<Box<S>>,
+        decoder: SseDecoder,
+        pending: VecDeque<Result<SseEvent, LLMError>>,
+        done: bool,
+    }
+
+    let state = State {
+        inner: Box::pin(bytes),
+        decoder: SseDecoder::new(),
+        pending: VecDeque::new(),
+        done: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        loop {
+            if let Some(item) = state.pending.pop_front() {
+                return Some((item, state));
+            }
+            if state.done {
+                return None;
+            }
+            match state.inner.next().await {
+                Some(Ok(bytes)) => {
+                    let events = state.decoder.push(bytes.as_ref());
+                    state.pending.extend(events.into_iter().map(Ok));
+                }
+                Some(Err(e)) => {
+                    state
+                        .pending
+                        .push_back(Err(LLMError::NetworkError(e.to_string())));
+                }
+                None => {
+                    state.done = true;
+                    let events = state.decoder.finish();
+                    state.pending.extend(events.into_iter().map(Ok));
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_in_chunks(input: &[u8], chunk_size: usize) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events = Vec::new();
+        for chunk in input.chunks(chunk_size) {
+            events.extend(decoder.push(chunk));
+        }
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn data(event: &SseEvent) -> &str {
+        event.data.as_str()
+    }
+
+    #[test]
+    fn test_event_fields() {
+        let input =
+            b"event: message_start\nid: 7\ndata: {\"a\":1}\n\n: keep-alive\n\ndata:no-space\n\n";
+        let events = decode_in_chunks(input, input.len());
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].event.as_deref(), Some("message_start"));
+        assert_eq!(events[0].id.as_deref(), Some("7"));
+        assert_eq!(data(&events[0]), "{\"a\":1}");
+        assert_eq!(events[1].event, None);
+        assert_eq!(data(&events[1]), "no-space");
+    }
+
+    #[test]
+    fn test_multi_line_data() {
+        let events = decode_in_chunks(b"data: first\ndata: second\n\n", 64);
+        assert_eq!(events.len(), 1);
+        assert_eq!(data(&events[0]), "first\nsecond");
+    }
+
+    #[test]
+    fn test_every_split_point_gives_same_events() {
+        let input =
+            "event: delta\r\ndata: {\"text\":\"héllo 👋\"}\r\n\r\ndata: [DONE]\r\n\r\n".as_bytes();
+        let expected = decode_in_chunks(input, input.len());
+        assert_eq!(expected.len(), 2);
+        assert_eq!(data(&expected[0]), "{\"text\":\"héllo 👋\"}");
+        for chunk_size in 1..input.len() {
+            assert_eq!(decode_in_chunks(input, chunk_size), expected);
+        }
+    }
+
+    #[test]
+    fn test_split_multibyte_character() {
+        let input = "data: 日本語\n\n".as_bytes();
+        // split inside the first character
+        let mut decoder = SseDecoder::new();
+        assert!(decoder.push(&input[..7]).is_empty());
+        let events = decoder.push(&input[7..]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(data(&events[0]), "日本語");
+    }
+
+    #[test]
+    fn test_cr_line_endings_and_missing_final_blank_line() {
+        let events = decode_in_chunks(b"data: a\r\rdata: b", 1);
+        assert_eq!(events.len(), 2);
+        assert_eq!(data(&events[0]), "a");
+        assert_eq!(data(&events[1]), "b");
+    }
+
+    #[test]
+    fn test_blank_lines_without_data_are_not_events() {
+        let events = decode_in_chunks(b"\n\nevent: ping\n\n", 3);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_empty_data_is_not_an_event() {
+        let input = b"event: ping\ndata:\n\ndata\n\ndata:\ndata:\n\ndata: x\n\n";
+        for chunk_size in 1..=input.len() {
+            let events = decode_in_chunks(input, chunk_size);
+            // two empty `data` lines still make one newline of data
+            assert_eq!(events.len(), 2);
+            assert_eq!(data(&events[0]), "\n");
+            assert_eq!(events[1].event, None);
+            assert_eq!(data(&events[1]), "x");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sse_stream_from_chunks() {
+        let chunks: Vec<Result<Vec<u8>, String>> = vec![
+            Ok(b"data: he".to_vec()),
+            Ok(b"llo\n".to_vec()),
+            Ok(b"\ndata: wor".to_vec()),
+            Err("connection reset".to_string()),
+            Ok(b"ld\n\n".to_vec()),
+        ];
+        let results: Vec<Result<SseEvent, LLMError>> =
+            sse_stream(stream::iter(chunks)).collect().await;
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().data, "hello");
+        assert!(matches!(results[1], Err(LLMError::NetworkError(_))));
+        assert_eq!(results[2].as_ref().unwrap().data, "world");
+    }
+}
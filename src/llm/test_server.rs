@@ -0,0 +1,102 @@
+//! Minimal HTTP server standing in for LLM APIs in provider tests
+
+use std::sync::{Arc, Mutex};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+    time::{sleep, Duration},
+};
+
+/// A request received by the test server
+#[derive(Debug, Clone, Default)]
+pub struct RecordedRequest {
+    pub request_line: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RecordedRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Serve a single request with `status` and a body written in `chunks`,
+/// pausing between chunks so the client sees them separately.
+/// Returns the base URL and a handle to the recorded request.
+pub async fn serve_once(
+    status: u16,
+    content_type: &str,
+    chunks: Vec<Vec<u8>>,
+) -> (String, Arc<Mutex<RecordedRequest>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let recorded = Arc::new(Mutex::new(RecordedRequest::default()));
+    let recorded_clone = recorded.clone();
+    let content_type = content_type.to_string();
+
+    tokio::spawn(async move {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        *recorded_clone.lock().unwrap() = read_request(&mut socket).await;
+
+        let head = format!(
+            "HTTP/1.1 {} Test\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+            status, content_type
+        );
+        socket.write_all(head.as_bytes()).await.unwrap();
+        for chunk in chunks {
+            socket.write_all(&chunk).await.unwrap();
+            socket.flush().await.unwrap();
+            sleep(Duration::from_millis(5)).await;
+        }
+        socket.shutdown().await.ok();
+    });
+
+    (format!("http://{}", addr), recorded)
+}
+
+async fn read_request(socket: &mut tokio::net::TcpStream) -> RecordedRequest {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+    let header_end = loop {
+        let n = socket.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            return RecordedRequest::default();
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).into_owned();
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let n = socket.read(&mut chunk).await.unwrap();
+        if n == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..n]);
+    }
+    let body = String::from_utf8_lossy(&buffer[header_end..]).into_owned();
+
+    RecordedRequest {
+        request_line,
+        headers,
+        body,
+    }
+}
@@ -1,6 +1,4 @@
-use dotenv::dotenv;
 use futures::stream::StreamExt;
-use regex::Regex;
 use std::{
     env::{
         self,
@@ -8,95 +6,345 @@ use std::{
     },
     error::Error,
     io::{self, BufRead},
+    path::Path,
     process,
 };
 
+mod budget;
+mod config;
+mod context;
+mod extract;
+mod history;
+mod init;
 mod llm;
+mod output;
+mod picker;
 mod prompts;
+mod redact;
+mod safety;
+mod session;
+mod usage;
 
-use llm::{create_provider, LLMConfig, LLMError, LLMProvider};
+use config::{ConfigFile, Profile};
+use context::PaneConfig;
+use init::Shell;
+use llm::{
+    create_provider,
+    retry::{self, RetryPolicy},
+    ChatMessage, LLMConfig, LLMError, LLMProvider, StopReason, StreamError, StreamEvent, Usage,
+    DEFAULT_MAX_TOKENS,
+};
+use output::{AnswerOutput, OutputFormat, StreamLine};
+use safety::DangerPolicy;
+use usage::{BudgetAction, BudgetConfig};
 
 // args
 const ARG_DEBUG: &str = "--debug_ask_sh";
 const ARG_NO_PANE: &str = "--no_pane";
 const ARG_NO_SUGGEST: &str = "--no_suggest";
+const ARG_NO_HISTORY: &str = "--no_history";
 const ARG_VERSION: &str = "--version";
 const ARG_VERSION_SHORT: &str = "-v";
+const ARG_CONTINUE: &str = "--continue";
+const ARG_NEW_SESSION: &str = "--new-session";
+const ARG_LIST_SESSIONS: &str = "--list-sessions";
+const ARG_SHOW_REDACTIONS: &str = "--show-redactions";
 
 const ARG_STRINGS: &[&str] = &[
     ARG_DEBUG,
     ARG_NO_PANE,
     ARG_NO_SUGGEST,
+    ARG_NO_HISTORY,
     ARG_VERSION,
     ARG_VERSION_SHORT,
+    ARG_CONTINUE,
+    ARG_NEW_SESSION,
+    ARG_LIST_SESSIONS,
+    ARG_SHOW_REDACTIONS,
 ];
 
+// args followed by a value
+const ARG_SESSION: &str = "--session";
+const ARG_PROFILE: &str = "--profile";
+const ARG_FORMAT: &str = "--format";
+
+const ARG_VALUE_STRINGS: &[&str] = &[ARG_SESSION, ARG_PROFILE, ARG_FORMAT];
+
 // special arg
 const ARG_INIT: &str = "--init";
 
+// subcommand
+const SUBCOMMAND_PICK: &str = "pick";
+const SUBCOMMAND_USAGE: &str = "usage";
+
 // env
 const ENV_DEBUG: &str = "ASK_SH_DEBUG";
 const ENV_NO_PANE: &str = "ASK_SH_NO_PANE";
 const ENV_NO_SUGGEST: &str = "ASK_SH_NO_SUGGEST";
+const ENV_NO_HISTORY: &str = "ASK_SH_NO_HISTORY";
+const ENV_HISTORY: &str = "ASK_SH_HISTORY";
+const ENV_LAST_STATUS: &str = "ASK_SH_LAST_STATUS";
+const ENV_STATE_FILE: &str = "ASK_SH_STATE_FILE";
+const ENV_CONTINUE: &str = "ASK_SH_CONTINUE";
+const ENV_PROFILE: &str = "ASK_SH_PROFILE";
+const ENV_DANGER_POLICY: &str = "ASK_SH_DANGER_POLICY";
+const ENV_SHELL: &str = "ASK_SH_SHELL";
+const ENV_CONTEXT_SOURCE: &str = "ASK_SH_CONTEXT_SOURCE";
+const ENV_SCROLLBACK: &str = "ASK_SH_SCROLLBACK";
+const ENV_PANE_MAX_TOKENS: &str = "ASK_SH_PANE_MAX_TOKENS";
+const ENV_CONTEXT_WINDOW: &str = "ASK_SH_CONTEXT_WINDOW";
+const ENV_MONTHLY_BUDGET: &str = "ASK_SH_MONTHLY_BUDGET";
+const ENV_BUDGET_ACTION: &str = "ASK_SH_BUDGET_ACTION";
+const ENV_FALLBACK: &str = "ASK_SH_FALLBACK";
+const ENV_MAX_RETRIES: &str = "ASK_SH_MAX_RETRIES";
 
 // LLM provider settings
 const ENV_LLM_PROVIDER: &str = "ASK_SH_LLM_PROVIDER";
 const ENV_OPENAI_API_KEY: &str = "ASK_SH_OPENAI_API_KEY";
 const ENV_OPENAI_MODEL: &str = "ASK_SH_OPENAI_MODEL";
 const ENV_OPENAI_BASE_URL: &str = "ASK_SH_OPENAI_BASE_URL";
+const ENV_OPENAI_STREAM_USAGE: &str = "ASK_SH_OPENAI_STREAM_USAGE";
 const ENV_ANTHROPIC_API_KEY: &str = "ASK_SH_ANTHROPIC_API_KEY";
 const ENV_ANTHROPIC_MODEL: &str = "ASK_SH_ANTHROPIC_MODEL";
+const ENV_ANTHROPIC_BASE_URL: &str = "ASK_SH_ANTHROPIC_BASE_URL";
 const ENV_NANOGPT_API_KEY: &str = "ASK_SH_NANOGPT_API_KEY";
 const ENV_NANOGPT_MODEL: &str = "ASK_SH_NANOGPT_MODEL";
+const ENV_NANOGPT_BASE_URL: &str = "ASK_SH_NANOGPT_BASE_URL";
+const ENV_OLLAMA_MODEL: &str = "ASK_SH_OLLAMA_MODEL";
+const ENV_OLLAMA_BASE_URL: &str = "ASK_SH_OLLAMA_BASE_URL";
+const ENV_OLLAMA_NUM_CTX: &str = "ASK_SH_OLLAMA_NUM_CTX";
+const ENV_OLLAMA_KEEP_ALIVE: &str = "ASK_SH_OLLAMA_KEEP_ALIVE";
+const ENV_GEMINI_API_KEY: &str = "ASK_SH_GEMINI_API_KEY";
+const ENV_GEMINI_MODEL: &str = "ASK_SH_GEMINI_MODEL";
+const ENV_GEMINI_BASE_URL: &str = "ASK_SH_GEMINI_BASE_URL";
+const ENV_AZURE_OPENAI_API_KEY: &str = "ASK_SH_AZURE_OPENAI_API_KEY";
+const ENV_AZURE_OPENAI_ENDPOINT: &str = "ASK_SH_AZURE_OPENAI_ENDPOINT";
+const ENV_AZURE_OPENAI_DEPLOYMENT: &str = "ASK_SH_AZURE_OPENAI_DEPLOYMENT";
+const ENV_AZURE_OPENAI_API_VERSION: &str = "ASK_SH_AZURE_OPENAI_API_VERSION";
+const ENV_MOCK_FIXTURE: &str = "ASK_SH_MOCK_FIXTURE";
+const ENV_RECORD: &str = "ASK_SH_RECORD";
+const ENV_MAX_TOKENS: &str = "ASK_SH_MAX_TOKENS";
+const ENV_TEMPERATURE: &str = "ASK_SH_TEMPERATURE";
+
+/// Returns the API key from `env_key`, or from the profile
+fn get_api_key(env_key: &str, profile: &Profile, label: &str) -> Result<String, LLMError> {
+    match env::var(env_key) {
+        Ok(api_key) => Ok(api_key),
+        Err(_) => profile
+            .api_key()?
+            .ok_or_else(|| LLMError::ConfigError(format!("{} API key not found", label))),
+    }
+}
+
+/// Returns the env var `key` parsed, or the profile value
+fn get_env_or<T: std::str::FromStr>(key: &str, fallback: Option<T>) -> Result<Option<T>, LLMError> {
+    match env::var(key) {
+        Ok(val) => val
+            .parse::<T>()
+            .map(Some)
+            .map_err(|_| LLMError::ConfigError(format!("Invalid value for {}: {}", key, val))),
+        Err(_) => Ok(fallback),
+    }
+}
+
+/// Config of the first provider. `explicit` is true when the profile was selected with
+/// --profile or ASK_SH_PROFILE: its provider then wins over ASK_SH_LLM_PROVIDER.
+fn get_llm_config(profile: &Profile, explicit: bool) -> Result<LLMConfig, LLMError> {
+    // Select provider (explicit profile, then env, then default profile; default is OpenAI)
+    let provider = match &profile.provider {
+        Some(provider) if explicit => provider.clone(),
+        _ => env::var(ENV_LLM_PROVIDER)
+            .ok()
+            .or_else(|| profile.provider.clone())
+            .unwrap_or_else(|| "openai".to_string()),
+    };
+
+    // Model settings of the profile only apply to the provider it was written for
+    let empty_profile = Profile::default();
+    let profile = match &profile.provider {
+        Some(profile_provider) if *profile_provider != provider => &empty_profile,
+        _ => profile,
+    };
+
+    get_provider_config(provider, profile)
+}
 
-fn get_llm_config() -> Result<LLMConfig, LLMError> {
-    dotenv().ok();
+/// Config of a profile in the fallback chain: ASK_SH_LLM_PROVIDER selects the first provider
+/// only, so the profile keeps its own
+fn get_fallback_config(profile: &Profile) -> Result<LLMConfig, LLMError> {
+    let provider = profile
+        .provider
+        .clone()
+        .unwrap_or_else(|| "openai".to_string());
+    get_provider_config(provider, profile)
+}
 
-    // Select provider (default is OpenAI)
-    let provider = env::var(ENV_LLM_PROVIDER).unwrap_or_else(|_| "openai".to_string());
+fn get_provider_config(provider: String, profile: &Profile) -> Result<LLMConfig, LLMError> {
+    let max_tokens = get_env_or(ENV_MAX_TOKENS, profile.max_tokens)?;
+    let temperature = get_env_or(ENV_TEMPERATURE, profile.temperature)?;
 
     match provider.as_str() {
         "openai" => {
-            let api_key = env::var(ENV_OPENAI_API_KEY)
-                .map_err(|_| LLMError::ConfigError("OpenAI API key not found".to_string()))?;
+            let api_key = get_api_key(ENV_OPENAI_API_KEY, profile, "OpenAI")?;
 
-            let model = env::var(ENV_OPENAI_MODEL).unwrap_or_else(|_| "gpt-3.5-turbo".to_string());
+            let model = env::var(ENV_OPENAI_MODEL)
+                .ok()
+                .or_else(|| profile.model.clone())
+                .unwrap_or_else(|| "gpt-3.5-turbo".to_string());
 
-            let base_url = env::var(ENV_OPENAI_BASE_URL).ok();
+            let base_url = env::var(ENV_OPENAI_BASE_URL)
+                .ok()
+                .or_else(|| profile.base_url.clone());
 
             Ok(LLMConfig {
                 provider,
                 api_key,
                 model,
                 base_url,
+                max_tokens,
+                temperature,
+                stream_usage: get_env_or(ENV_OPENAI_STREAM_USAGE, profile.stream_usage)?,
+                ..Default::default()
             })
         }
         "anthropic" => {
-            let api_key = env::var(ENV_ANTHROPIC_API_KEY)
-                .map_err(|_| LLMError::ConfigError("Anthropic API key not found".to_string()))?;
+            let api_key = get_api_key(ENV_ANTHROPIC_API_KEY, profile, "Anthropic")?;
 
             let model = env::var(ENV_ANTHROPIC_MODEL)
-                .unwrap_or_else(|_| "claude-3-5-sonnet-latest".to_string());
+                .ok()
+                .or_else(|| profile.model.clone())
+                .unwrap_or_else(|| "claude-3-5-sonnet-latest".to_string());
+
+            let base_url = env::var(ENV_ANTHROPIC_BASE_URL)
+                .ok()
+                .or_else(|| profile.base_url.clone());
 
             Ok(LLMConfig {
                 provider,
                 api_key,
                 model,
-                base_url: None, // Anthropic does not support custom endpoints
+                base_url,
+                max_tokens,
+                temperature,
+                ..Default::default()
             })
         }
         "nanogpt" => {
-            let api_key = env::var(ENV_NANOGPT_API_KEY)
-                .map_err(|_| LLMError::ConfigError("NanoGPT API key not found".to_string()))?;
+            let api_key = get_api_key(ENV_NANOGPT_API_KEY, profile, "NanoGPT")?;
 
             // Qwen turbo is a cheap and fast model. Does the job for most cases.
-            let model = env::var(ENV_NANOGPT_MODEL).unwrap_or_else(|_| "gpt-4o".to_string());
+            let model = env::var(ENV_NANOGPT_MODEL)
+                .ok()
+                .or_else(|| profile.model.clone())
+                .unwrap_or_else(|| "gpt-4o".to_string());
+
+            let base_url = env::var(ENV_NANOGPT_BASE_URL)
+                .ok()
+                .or_else(|| profile.base_url.clone());
+
+            Ok(LLMConfig {
+                provider,
+                api_key,
+                model,
+                base_url,
+                max_tokens,
+                temperature,
+                ..Default::default()
+            })
+        }
+        "ollama" => {
+            // Ollama runs locally and needs no API key
+            let model = env::var(ENV_OLLAMA_MODEL)
+                .ok()
+                .or_else(|| profile.model.clone())
+                .unwrap_or_else(|| "llama3.2".to_string());
+
+            let base_url = env::var(ENV_OLLAMA_BASE_URL)
+                .ok()
+                .or_else(|| profile.base_url.clone());
+
+            Ok(LLMConfig {
+                provider,
+                api_key: String::new(),
+                model,
+                base_url,
+                max_tokens,
+                temperature,
+                num_ctx: get_env_or(ENV_OLLAMA_NUM_CTX, profile.num_ctx)?,
+                keep_alive: env::var(ENV_OLLAMA_KEEP_ALIVE)
+                    .ok()
+                    .or_else(|| profile.keep_alive.clone()),
+                ..Default::default()
+            })
+        }
+        "gemini" => {
+            let api_key = get_api_key(ENV_GEMINI_API_KEY, profile, "Gemini")?;
+
+            let model = env::var(ENV_GEMINI_MODEL)
+                .ok()
+                .or_else(|| profile.model.clone())
+                .unwrap_or_else(|| "gemini-1.5-flash".to_string());
+
+            let base_url = env::var(ENV_GEMINI_BASE_URL)
+                .ok()
+                .or_else(|| profile.base_url.clone());
 
             Ok(LLMConfig {
                 provider,
                 api_key,
                 model,
-                base_url: None, // NanoGPT does not support custom endpoints
+                base_url,
+                max_tokens,
+                temperature,
+                ..Default::default()
+            })
+        }
+        "azure" => {
+            let api_key = get_api_key(ENV_AZURE_OPENAI_API_KEY, profile, "Azure OpenAI")?;
+
+            // e.g. https://my-resource.openai.azure.com
+            let endpoint = env::var(ENV_AZURE_OPENAI_ENDPOINT)
+                .ok()
+                .or_else(|| profile.base_url.clone())
+                .ok_or_else(|| {
+                    LLMError::ConfigError("Azure OpenAI endpoint not found".to_string())
+                })?;
+
+            // Azure selects the model by deployment, so the deployment name is used as model
+            let deployment = env::var(ENV_AZURE_OPENAI_DEPLOYMENT)
+                .ok()
+                .or_else(|| profile.model.clone())
+                .ok_or_else(|| {
+                    LLMError::ConfigError("Azure OpenAI deployment not found".to_string())
+                })?;
+
+            let api_version = env::var(ENV_AZURE_OPENAI_API_VERSION)
+                .ok()
+                .or_else(|| profile.api_version.clone());
+
+            Ok(LLMConfig {
+                provider,
+                api_key,
+                model: deployment,
+                base_url: Some(endpoint),
+                max_tokens,
+                temperature,
+                api_version,
+                ..Default::default()
+            })
+        }
+        "mock" => {
+            // Replays a fixture file instead of calling an API (for tests)
+            let fixture = env::var(ENV_MOCK_FIXTURE)
+                .ok()
+                .or_else(|| profile.base_url.clone())
+                .ok_or_else(|| LLMError::ConfigError("Mock fixture not found".to_string()))?;
+
+            Ok(LLMConfig {
+                provider,
+                model: profile.model.clone().unwrap_or_else(|| "mock".to_string()),
+                base_url: Some(fixture),
+                ..Default::default()
             })
         }
         _ => Err(LLMError::ConfigError(format!(
@@ -106,11 +354,134 @@ fn get_llm_config() -> Result<LLMConfig, LLMError> {
     }
 }
 
-fn get_env_flag(key: &str) -> bool {
-    dotenv().ok();
+/// Flag from the env var `key`, or the profile value when the env var is not set
+fn get_env_flag(key: &str, fallback: Option<bool>) -> bool {
     match env::var(key) {
         Ok(val) => val.parse::<bool>().unwrap_or(false),
-        Err(_e) => false,
+        Err(_e) => fallback.unwrap_or(false),
+    }
+}
+
+/// Returns the `[pane]` table with the env vars applied
+fn get_pane_config(config: &PaneConfig) -> Result<PaneConfig, LLMError> {
+    Ok(PaneConfig {
+        scrollback: get_env_or(ENV_SCROLLBACK, config.scrollback)?,
+        max_tokens: get_env_or(ENV_PANE_MAX_TOKENS, config.max_tokens)?,
+        ..config.clone()
+    })
+}
+
+/// Returns the `[budget]` table with the env vars applied
+fn get_budget_config(config: &BudgetConfig) -> Result<BudgetConfig, LLMError> {
+    Ok(BudgetConfig {
+        monthly: get_env_or(ENV_MONTHLY_BUDGET, config.monthly)?,
+        action: get_env_or(ENV_BUDGET_ACTION, config.action)?,
+    })
+}
+
+/// Returns the value following `flag`, if any
+fn get_arg_value(words: &[&str], flag: &str) -> Option<String> {
+    words
+        .iter()
+        .position(|word| *word == flag)
+        .and_then(|i| words.get(i + 1))
+        .map(|value| value.to_string())
+}
+
+/// Removes predefined args, and the values of args that take one
+fn strip_args<'a>(words: &[&'a str]) -> Vec<&'a str> {
+    let mut stripped = Vec::new();
+    let mut skip_value = false;
+    for word in words {
+        if skip_value {
+            skip_value = false;
+        } else if ARG_VALUE_STRINGS.contains(word) {
+            skip_value = true;
+        } else if !ARG_STRINGS.contains(word) {
+            stripped.push(*word);
+        }
+    }
+    stripped
+}
+
+fn print_sessions() {
+    let store = match session::SessionStore::open_default() {
+        Some(store) => store,
+        None => {
+            eprintln!("Could not locate the session directory. Is $HOME set?");
+            return;
+        }
+    };
+    match store.list() {
+        Ok(summaries) if summaries.is_empty() => eprintln!("No sessions yet."),
+        Ok(summaries) => {
+            let current = session::default_session_name();
+            for summary in summaries {
+                let marker = if summary.name == current { "*" } else { " " };
+                eprintln!(
+                    "{} {}\t{} exchanges\t{}\t{}",
+                    marker,
+                    summary.name,
+                    summary.exchanges,
+                    session::format_age(summary.last_used),
+                    summary.last_input
+                );
+            }
+        }
+        Err(e) => eprintln!("Could not read sessions: {}", e),
+    }
+}
+
+/// Summarizes the usage ledger for `month` (default: the current one), for `ask-sh usage`
+fn print_usage(month: Option<String>) -> i32 {
+    let budget = match ConfigFile::load()
+        .map_err(|e| e.to_string())
+        .and_then(|config_file| get_budget_config(&config_file.budget).map_err(|e| e.to_string()))
+    {
+        Ok(budget) => budget,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let ledger = match usage::Ledger::open_default() {
+        Some(ledger) => ledger,
+        None => {
+            eprintln!("Could not locate the usage ledger. Is $HOME set?");
+            return 1;
+        }
+    };
+    match ledger.load() {
+        Ok(records) => {
+            let month = month.unwrap_or_else(|| usage::date(session::now())[..7].to_string());
+            print!("{}", usage::report(&records, &month, budget.monthly));
+            0
+        }
+        Err(e) => {
+            eprintln!("Could not read the usage ledger: {}", e);
+            1
+        }
+    }
+}
+
+/// Lists what was redacted, for --show-redactions
+fn print_redactions(sources: &[(&str, &Vec<redact::Redaction>)]) {
+    let count: usize = sources.iter().map(|(_, redactions)| redactions.len()).sum();
+    if count == 0 {
+        eprintln!("*** Note: Nothing was redacted. ***");
+        return;
+    }
+    eprintln!("*** Note: {} secret(s) redacted before sending: ***", count);
+    for (source, redactions) in sources {
+        for redaction in redactions.iter() {
+            eprintln!(
+                "  {} line {}: {} {}",
+                source,
+                redaction.line,
+                redaction.detector,
+                redaction.preview()
+            );
+        }
     }
 }
 
@@ -121,157 +492,247 @@ struct UserInfo {
     // TODO: add distro info if linux
 }
 
+/// Response collected from the LLM provider
+struct ChatResponse {
+    /// Index of the attempt that answered
+    attempt: usize,
+    /// The provider of the fallback chain that answered
+    provider: String,
+    model: String,
+    text: String,
+    stop_reason: Option<StopReason>,
+    usage: Usage,
+    errors: Vec<StreamError>,
+}
+
+/// A provider of the fallback chain, with the request fitted to its context window
+struct Attempt {
+    config: LLMConfig,
+    estimator: budget::Estimator,
+    messages: Vec<ChatMessage>,
+}
+
+/// Build the messages for `config`, shortened to fit in `context_window` with room left for
+/// the answer
+fn fit_request<F>(
+    config: LLMConfig,
+    context_window: usize,
+    vars: &std::collections::HashMap<String, String>,
+    exchanges: &[session::Exchange],
+    build: F,
+    debug_mode: bool,
+) -> Attempt
+where
+    F: Fn(&std::collections::HashMap<String, String>, &[session::Exchange]) -> Vec<ChatMessage>,
+{
+    // room left for the answer
+    let reserved = config.max_tokens.map_or(
+        (DEFAULT_MAX_TOKENS as usize).min(context_window / 4),
+        |max_tokens| max_tokens as usize,
+    );
+    let estimator = budget::Estimator::for_model(&config.provider, &config.model);
+    let (messages, truncations) = budget::fit(
+        &mut vars.clone(),
+        &mut exchanges.to_vec(),
+        context_window.saturating_sub(reserved),
+        &estimator,
+        build,
+    );
+
+    if debug_mode {
+        eprintln!(
+            "context_window of {} ({}): {}",
+            config.provider, config.model, context_window
+        );
+        eprintln!(
+            "prompt_tokens (estimated): {}",
+            estimator.messages(&messages)
+        );
+        eprintln!("reserved for the answer: {}", reserved);
+        for truncation in &truncations {
+            eprintln!(
+                "truncated {}: {} tokens removed",
+                truncation.part, truncation.removed_tokens
+            );
+        }
+    }
+    Attempt {
+        config,
+        estimator,
+        messages,
+    }
+}
+
 /// Chat with LLM provider
 #[tokio::main]
 async fn chat(
-    user_input: String,
-    system_message: String,
-    _debug_mode: &bool, // currently unused
-) -> Result<String, Box<dyn Error>> {
-    let config = get_llm_config().map_err(|e| Box::new(e) as Box<dyn Error>)?;
-    let provider = create_provider(config).map_err(|e| Box::new(e) as Box<dyn Error>)?;
-
-    let mut stream = LLMProvider::chat_stream(&provider, system_message, user_input)
-        .await
-        .map_err(|e| Box::new(e) as Box<dyn Error>)?;
-
-    let mut response_to_return = String::new();
+    attempts: &[Attempt],
+    record_path: Option<&str>,
+    format: OutputFormat,
+    retry_policy: &RetryPolicy,
+    debug_mode: &bool,
+) -> Result<ChatResponse, Box<dyn Error>> {
+    // the first provider of the chain that starts answering, after retries
+    let mut answering = None;
+    let mut attempts = attempts.iter().enumerate().peekable();
+    while let Some((index, attempt)) = attempts.next() {
+        let messages = &attempt.messages;
+        let attempt = match create_provider(attempt.config.clone()) {
+            Ok(provider) => {
+                if *debug_mode {
+                    eprintln!("provider: {}", provider.name());
+                    eprintln!("model: {}", provider.model());
+                    eprintln!("messages: {}", messages.len());
+                }
+                let name = provider.name();
+                retry::chat_stream(&provider, messages, retry_policy, |error, delay| {
+                    eprintln!(
+                        "*** Note: {} failed ({}). Retrying in {:.1}s. ***",
+                        name,
+                        error,
+                        delay.as_secs_f64()
+                    )
+                })
+                .await
+                .map(|stream| (index, provider, stream))
+            }
+            Err(e) => Err(e),
+        };
+        match (attempt, attempts.peek().map(|(_, next)| &next.config)) {
+            (Ok(answer), _) => {
+                answering = Some(answer);
+                break;
+            }
+            (Err(e), Some(next)) => eprintln!(
+                "*** Note: Falling back to {} ({}) after: {} ***",
+                next.provider, next.model, e
+            ),
+            (Err(e), None) => return Err(Box::new(e) as Box<dyn Error>),
+        }
+    }
+    let (attempt, provider, mut stream) = answering.ok_or("No LLM provider configured")?;
+    // save the stream as a fixture for the mock provider
+    if let Some(path) = record_path {
+        stream = llm::mock::record(stream, Path::new(path))
+            .map_err(|e| Box::new(e) as Box<dyn Error>)?;
+    }
+
+    let mut response = ChatResponse {
+        attempt,
+        provider: provider.name().to_string(),
+        model: provider.model().to_string(),
+        text: String::new(),
+        stop_reason: None,
+        usage: Usage::default(),
+        errors: Vec::new(),
+    };
+    // in the JSON formats, the answer goes to stdout and only notices to stderr
+    let ndjson = format == OutputFormat::Ndjson;
     while let Some(result) = stream.next().await {
         match result {
-            Ok(content) => {
-                response_to_return.push_str(&content);
-                eprint!("{}", content);
+            Ok(StreamEvent::Text(content)) => {
+                response.text.push_str(&content);
+                match format {
+                    OutputFormat::Text => eprint!("{}", content),
+                    OutputFormat::Json => {}
+                    OutputFormat::Ndjson => StreamLine::Text { text: &content }.print(),
+                }
+            }
+            Ok(StreamEvent::Stop(reason)) => {
+                if ndjson {
+                    StreamLine::Stop {
+                        reason: reason.as_str(),
+                    }
+                    .print();
+                }
+                response.stop_reason = Some(reason);
+            }
+            Ok(StreamEvent::Usage(usage)) => {
+                if ndjson {
+                    StreamLine::Usage(usage).print();
+                }
+                response.usage.merge(usage);
+            }
+            Ok(StreamEvent::Error(error)) => {
+                if ndjson {
+                    StreamLine::error(&error).print();
+                }
+                if error.is_overloaded() {
+                    eprint!(
+                        "\n*** {} is overloaded right now ({}). Please try again later. ***",
+                        provider.name(),
+                        error.message
+                    );
+                } else {
+                    eprint!(
+                        "\n*** {} reported an error ({}): {} ***",
+                        provider.name(),
+                        error.kind,
+                        error.message
+                    );
+                }
+                response.errors.push(error);
             }
             Err(err) => {
                 eprint!("{}", err);
+                let error = StreamError {
+                    kind: "transport".to_string(),
+                    message: err.to_string(),
+                };
+                if ndjson {
+                    StreamLine::error(&error).print();
+                }
+                response.errors.push(error);
             }
         }
     }
-    Ok(response_to_return)
-}
 
-fn post_process(text: &str) -> Vec<String> {
-    let mut commands = Vec::new();
-    // extract all commands enclosed in ``` ```
-    let re = Regex::new(r#"```(.+?)```"#).unwrap();
-    re.captures_iter(&text.replace('\n', ";")).for_each(|cap| {
-        commands.push(
-            cap[1]
-                .to_string()
-                .replace('\n', " ")
-                .trim_start_matches(';')
-                .trim_end_matches(';')
-                .trim()
-                .to_owned(),
-        );
-    });
-    // if command start from bash; or sh; remove it
-    commands = commands
-        .iter()
-        .map(|command| {
-            if command.starts_with("bash;") {
-                command.trim_start_matches("bash;").trim().to_owned()
-            } else if command.starts_with("sh;") {
-                command.trim_start_matches("sh;").trim().to_owned()
-            } else {
-                command.to_owned()
-            }
-        })
-        .collect();
-    // deduplicate with keeping the order
-    // count the number of occurences of each command
-    let mut counts = std::collections::HashMap::new();
-    for command in &commands {
-        let count = counts.entry(command).or_insert(0);
-        *count += 1;
-    }
-    // add only the first occurence of each command to deduped_commands
-    // TODO: not elegant
-    let mut deduped_commands: Vec<String> = Vec::new();
-    for command in &commands {
-        if deduped_commands.contains(command) {
-        } else {
-            deduped_commands.push(command.to_string());
+    match &response.stop_reason {
+        Some(StopReason::MaxTokens) => eprint!(
+            "\n*** Note: The response was truncated because it reached the output token limit. ***"
+        ),
+        Some(StopReason::ContentFilter) => {
+            eprint!("\n*** Note: The response was stopped by the provider's content filter. ***")
         }
+        _ => {}
     }
-    deduped_commands
-}
-
-fn print_init_script() {
-    print!(
-        r#"# This function is automatically generated by ask-sh --init
-# ask.sh shell function v2
-ask() {{
-    if ! command -v ask-sh &> /dev/null; then
-        printf "❌ Necessary rust package ask-sh is installed but cannot be accessed. Rust's bin path may not be added to your PATH."
-        printf "👉 It's usually under ~/.cargo/bin/"
-        printf "👀 Please add it to your PATH and restart your shell."
-    fi
-    suggested_commands=`echo "$@" | ask-sh 2> >(cat 1>&2)`
-    if [ -n "$suggested_commands" ]; then
-        printf "\n" # add one empty line to create space
-        printf "👋 Hey, AI has suggested some commands that can be typed into your terminal.\n"
-        printf "🔍 Press Enter to view and select the commands, or type any other key to exit:"
-        if [ -n "$ZSH_VERSION" ]; then # read a single char
-            read -r -k 1 REPLY # zsh
-        else
-            read -r -n 1 REPLY # bash
-        fi
-        REPLY="${{REPLY#"${{REPLY%%[![:space:]]*}}"}}"  # trim whitespaces
-        if [ -z "$REPLY" ] ; then
-            # As Enter will move cursor to the next line, we need to go back two lines
-            printf "\033[2A"
-            # \033[2K: delete current line (👋 line), \n: go next line, \033[2K: delete next line (🔍 line)
-            printf "\033[2K\n\033[2K\n"
-            # We're at the emptified 🔍 line. So, go back two lines, including empty line to make space
-            printf "\033[2A" # go back again
-            selected_command=`echo "$suggested_commands" | peco  --prompt "AI suggested commands (Enter to use / Ctrl+C to exit):"`
-            if [ -n "$selected_command" ]; then
-                if ! print -z $selected_command 2>/dev/null; then
-                    history -s $selected_command
-                fi
-            fi
-        else
-            # We're at the end of 🔍 line. So, go back one line (👋 line)
-            printf "\033[1A"
-            printf "\033[2K\n\033[2K\n"
-            printf "\033[2A"
-        fi
-    fi
-    if [ -z "$ASK_SH_NO_UPDATE" ]; then
-        latest_version=`cargo search ask-sh | grep ask-sh | awk '{{print $3}}' | cut -d '"' -f2`
-        current_version=`ask-sh --version`
-        if [ "$(printf '%s\n' "$latest_version" "$current_version" | sort -rV | head -n1)" = "$latest_version" ] && [ "$latest_version" != "$current_version" ]; then
-            # clear line
-            printf "\n"
-            printf "🎉 New version of ask-sh is available! (Current: $current_version vs New: $latest_version) Set \$ASK_SH_NO_UPDATE=1 to suppress this notice.\n"
-            printf "🆙 Press Enter to run update now, or type any other key to exit:"
-            if [ -n "$ZSH_VERSION" ]; then # read a single char
-                read -r -k 1 REPLY # zsh
-            else
-                read -r -n 1 REPLY # bash
-            fi
-            REPLY="${{REPLY#"${{REPLY%%[![:space:]]*}}"}}"  # trim whitespaces
-            if [ -z "$REPLY" ] ; then
-                cargo install --force ask-sh
-                printf "\nDone! Please restart your shell or source ~/.zshrc or ~/.bashrc etc... to use the new version.\n"
-            else
-                printf "\nOk, you can update ask-sh later by running 'cargo install --force ask-sh'.\n"
-            fi
-        fi
-    fi
-}}
-"#
-    );
+    if *debug_mode {
+        eprintln!();
+        eprintln!("stop_reason: {:?}", response.stop_reason);
+        eprintln!("usage: {:?}", response.usage);
+    }
+    Ok(response)
 }
 
 fn main() {
-    // if called with only --init, the command emits a shell script to be sourced
-    if env::args().len() == 2 && env::args().nth(1).unwrap() == ARG_INIT {
-        print_init_script();
+    // if called with only --init [shell], the command emits a shell script to be sourced
+    if (2..=3).contains(&env::args().len()) && env::args().nth(1).unwrap() == ARG_INIT {
+        let shell = match env::args().nth(2) {
+            Some(name) => name.parse().unwrap_or_else(|e| {
+                eprintln!("{}", e);
+                process::exit(1);
+            }),
+            None => Shell::detect(),
+        };
+        print!("{}", shell.init_script());
         return;
     }
 
+    // if called with only pick, let the user choose one of the commands on stdin
+    if env::args().len() == 2 && env::args().nth(1).unwrap() == SUBCOMMAND_PICK {
+        process::exit(picker::run());
+    }
+
+    // if called with only usage [YYYY-MM], summarize the token usage and cost
+    if (2..=3).contains(&env::args().len())
+        && env::args().nth(1).unwrap() == SUBCOMMAND_USAGE
+        && env::args()
+            .nth(2)
+            .is_none_or(|month| usage::is_month(&month))
+    {
+        process::exit(print_usage(env::args().nth(2)));
+    }
+
     // if called with only --version or -v, print version and exit
     if env::args().len() == 2 {
         let arg = env::args().nth(1).unwrap();
@@ -284,79 +745,214 @@ fn main() {
     // check input from users
     // arg without the first executable name
     let args: Vec<String> = env::args().skip(1).collect();
+    let arg_words: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    if arg_words.contains(&ARG_LIST_SESSIONS) {
+        print_sessions();
+        return;
+    }
+
     // check if args are all predefined args
-    let is_using_stdin = args.iter().all(|arg| ARG_STRINGS.contains(&arg.as_str()));
+    let is_using_stdin = strip_args(&arg_words).is_empty();
 
     let user_input = if is_using_stdin {
         io::stdin().lock().lines().next().unwrap().unwrap()
     } else {
         args.join(" ")
     };
+    let input_words: Vec<&str> = user_input.split_whitespace().collect();
+
+    if input_words.contains(&ARG_LIST_SESSIONS) {
+        print_sessions();
+        return;
+    }
 
     // filter out predefined args
-    let user_input_without_flags = user_input
-        .split_whitespace()
-        .filter(|arg| !ARG_STRINGS.contains(arg))
-        .collect::<Vec<&str>>()
-        .join(" ");
+    let user_input_without_flags = strip_args(&input_words).join(" ");
+
+    // profile from --profile, then ASK_SH_PROFILE, then default_profile of the config file
+    let profile_name = get_arg_value(&arg_words, ARG_PROFILE)
+        .or_else(|| get_arg_value(&input_words, ARG_PROFILE))
+        .or_else(|| env::var(ENV_PROFILE).ok());
+    let config_file = match ConfigFile::load() {
+        Ok(config_file) => config_file,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let profile = match config_file.profile(profile_name.as_deref()) {
+        Ok(profile) => profile,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // --format json / ndjson write the answer as JSON to stdout
+    let format = match get_arg_value(&arg_words, ARG_FORMAT)
+        .or_else(|| get_arg_value(&input_words, ARG_FORMAT))
+        .map(|value| value.parse::<OutputFormat>())
+        .transpose()
+    {
+        Ok(format) => format.unwrap_or(OutputFormat::Text),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // a named session implies continuing it
+    let named_session =
+        get_arg_value(&arg_words, ARG_SESSION).or_else(|| get_arg_value(&input_words, ARG_SESSION));
+    let session_name = named_session
+        .clone()
+        .unwrap_or_else(session::default_session_name);
+    let new_session =
+        arg_words.contains(&ARG_NEW_SESSION) || input_words.contains(&ARG_NEW_SESSION);
+    let continue_session = !new_session
+        && (named_session.is_some()
+            || arg_words.contains(&ARG_CONTINUE)
+            || input_words.contains(&ARG_CONTINUE)
+            || get_env_flag(ENV_CONTINUE, profile.continue_session));
 
     // debug_mode is true if args contains --debug_ASK_SH or stdin text contains "--debug_ASK_SH" or env var ASK_SH_DEBUG is defined
-    let debug_mode = env::args()
-        .any(|arg| arg == ARG_DEBUG || user_input.contains(ARG_DEBUG) || get_env_flag(ENV_DEBUG));
+    let debug_mode = env::args().any(|arg| {
+        arg == ARG_DEBUG || user_input.contains(ARG_DEBUG) || get_env_flag(ENV_DEBUG, None)
+    });
 
     // send_pane is false if args contains --no_pane or stdin text contains "--no_pane" or env var ASK_SH_NO_PANE is defined
     // send_pane is immutable in case tmux capture-pane -p fails
     let mut send_pane = !env::args().any(|arg| arg == ARG_NO_PANE)
         && !user_input.contains(ARG_NO_PANE)
-        && !get_env_flag(ENV_NO_PANE);
+        && !get_env_flag(ENV_NO_PANE, profile.no_pane);
 
     // no_suggest is true if args contains --no_suggest or stdin text contains "--no_suggest" or env var ASK_SH_NO_SUGGEST is defined
     let no_suggest = env::args().any(|arg| arg == ARG_NO_SUGGEST)
         || user_input.contains(ARG_NO_SUGGEST)
-        || get_env_flag(ENV_NO_SUGGEST);
+        || get_env_flag(ENV_NO_SUGGEST, profile.no_suggest);
+
+    // send_history is false if args contains --no_history or stdin text contains "--no_history" or env var ASK_SH_NO_HISTORY is defined
+    let send_history = !env::args().any(|arg| arg == ARG_NO_HISTORY)
+        && !user_input.contains(ARG_NO_HISTORY)
+        && !get_env_flag(ENV_NO_HISTORY, profile.no_history);
+
+    // what to do with commands flagged as dangerous
+    let danger_policy = match get_env_or(ENV_DANGER_POLICY, profile.danger_policy) {
+        Ok(policy) => policy.unwrap_or_default(),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // recent commands passed by the shell function, the last one with its exit status
+    let history_entries = if send_history {
+        let last_status = env::var(ENV_LAST_STATUS)
+            .ok()
+            .and_then(|status| status.parse().ok());
+        history::parse(&env::var(ENV_HISTORY).unwrap_or_default(), last_status)
+    } else {
+        Vec::new()
+    };
+    // the last command as recorded by the shell hooks
+    let last_command = env::var(ENV_STATE_FILE)
+        .ok()
+        .filter(|_| send_history)
+        .and_then(|path| history::LastCommand::read(Path::new(&path)));
+
+    // where the terminal contents come from, detected from the environment if not configured
+    let context_source = match get_env_or(ENV_CONTEXT_SOURCE, profile.context_source) {
+        Ok(source) => source.or_else(|| context::Backend::detect(|key| env::var(key).ok())),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+
+    // scrollback depth and token budget of the captured text
+    let pane_config = match get_pane_config(&config_file.pane) {
+        Ok(pane_config) => pane_config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
-    // run tmux capture-pane -p before anything is printed.
+    // capture the terminal before anything is printed.
     // if run with no_pane, pane_text is empty string.
-    // if run without no_pane, execute shell command tmux capture-pane -p, when the command fail, pane_text return empty string
-    // when fail, print error message to stderr
+    // when the capture fails, pane_text is empty string and the error is printed to stderr
     let mut pane_text: String = "".to_string();
     if send_pane {
-        {
-            // check if in tmux session
-            let mut in_tmux = false;
-            match env::var("TMUX") {
-                Ok(_value) => in_tmux = true,
-                Err(_e) => {
-                    eprintln!("*** Note: Terminal output is not sent to AI. Run this command inside tmux to enable the feature. See https://github.com/hmirin/ask.sh/blob/master/README.md#qa for more information. If you no longer want to see this message, run `ask` with --no_pane option or set ASK_SH_NO_PANE=true. ***\n")
-                }
-            }
-            if in_tmux {
-                match std::process::Command::new("tmux")
-                    .arg("capture-pane")
-                    .arg("-p")
-                    .output()
-                {
-                    Ok(output) => pane_text = String::from_utf8_lossy(&output.stdout).to_string(),
+        match context_source {
+            Some(backend) => {
+                let source = context::create_source(backend, &pane_config);
+                match source.capture(&context::SystemRunner) {
+                    Ok(text) => pane_text = text,
                     Err(e) => {
-                        eprintln!("Somehow tmux capture-pane -p failed: {}", e);
+                        eprintln!("Somehow capturing the {} pane failed: {}", source.name(), e)
                     }
                 }
             }
+            None if !history_entries.is_empty() || last_command.is_some() => {}
+            None => {
+                eprintln!("*** Note: Terminal output is not sent to AI. Run this command inside tmux, screen, Zellij, kitty or WezTerm to enable the feature. See https://github.com/hmirin/ask.sh/blob/master/README.md#qa for more information. If you no longer want to see this message, run `ask` with --no_pane option or set ASK_SH_NO_PANE=true. ***\n")
+            }
         }
     };
-    // remove last empty lines from pane_text
-    let mut pane_text = pane_text.trim_end().to_string();
-    // remove last line of pane_text
-    if !pane_text.is_empty() {
-        let pane_text_lines: Vec<&str> = pane_text.split('\n').collect();
-        let mut pane_text_lines = pane_text_lines;
-        pane_text_lines.pop();
-        pane_text = pane_text_lines.join("\n");
+
+    let config = match get_llm_config(&profile, profile_name.is_some()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Communication with LLM provider failed: {}", e);
+            output::print_failure(format, &e.to_string());
+            process::exit(1);
+        }
+    };
+    let provider_name = config.provider.clone();
+    let model = config.model.clone();
+
+    // token counts of the pane and the request are estimated the same way
+    let estimator = budget::Estimator::for_model(&provider_name, &model);
+
+    // without the prompt ask was typed on, trimmed to the budget
+    let pane_text = context::prepare(
+        &pane_text,
+        &pane_config,
+        last_command.as_ref().map(|last| last.command.as_str()),
+        &estimator,
+    );
+
+    // secrets on the screen or in the request must not leave the machine
+    let redactor = match redact::Redactor::new(&config_file.redaction) {
+        Ok(redactor) => redactor,
+        Err(e) => {
+            eprintln!("Invalid redaction pattern in the config file: {}", e);
+            process::exit(1);
+        }
+    };
+    let (pane_text, pane_redactions) = redactor.redact(&pane_text);
+    let (user_input_without_flags, input_redactions) = redactor.redact(&user_input_without_flags);
+    let (history_text, mut history_redactions) =
+        redactor.redact(&history::format(&history_entries));
+    let (last_command_text, last_command_redactions) = redactor.redact(
+        &last_command
+            .as_ref()
+            .map(|last| last.describe())
+            .unwrap_or_default(),
+    );
+    history_redactions.extend(last_command_redactions);
+    if arg_words.contains(&ARG_SHOW_REDACTIONS) || input_words.contains(&ARG_SHOW_REDACTIONS) {
+        print_redactions(&[
+            ("terminal", &pane_redactions),
+            ("request", &input_redactions),
+            ("history", &history_redactions),
+        ]);
     }
 
-    // get user's shell name
+    // get user's shell name, ASK_SH_SHELL is set by shell functions not running in $SHELL
     // when env::var("SHELL") is not set, use BASH_VERSION or ZSH_VERSION to guess the shell
-    let shell = match env::var("SHELL") {
+    let shell = match env::var(ENV_SHELL).or_else(|_| env::var("SHELL")) {
         Ok(value) => value,
         Err(_e) => {
             if env::var("BASH_VERSION").is_ok() {
@@ -397,44 +993,290 @@ fn main() {
         eprintln!("debug_mode: {}", debug_mode);
         eprintln!("no_suggest: {}", no_suggest);
         eprintln!("pane_text: {}", pane_text);
+        eprintln!("history: {}", history_text);
+        eprintln!("last_command: {}", last_command_text);
+        eprintln!("session: {}", session_name);
+        eprintln!("continue_session: {}", continue_session);
+    }
+
+    let session_store = session::SessionStore::open_default();
+    if let Some(store) = &session_store {
+        if new_session {
+            if let Err(e) = store.clear(&session_name) {
+                eprintln!("Could not reset session {}: {}", session_name, e);
+            }
+        }
     }
+    let exchanges = match &session_store {
+        Some(store) if continue_session => store.load(&session_name).unwrap_or_else(|e| {
+            eprintln!("Could not load session {}: {}", session_name, e);
+            Vec::new()
+        }),
+        _ => Vec::new(),
+    };
+
+    let known_window = budget::context_window(
+        &provider_name,
+        &model,
+        config.num_ctx,
+        &config_file.context_windows,
+    );
+    let context_window = match get_env_or(ENV_CONTEXT_WINDOW, Some(known_window)) {
+        Ok(window) => window.unwrap_or(known_window),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
 
     let templates = prompts::get_template();
     let mut vars = std::collections::HashMap::new();
     vars.insert("pane_text".to_owned(), pane_text.to_owned());
     vars.insert("user_input".to_owned(), user_input_without_flags.to_owned());
+    vars.insert("history".to_owned(), history_text.to_owned());
+    vars.insert("last_command".to_owned(), last_command_text.to_owned());
     vars.insert("user_os".to_owned(), user_info.os.to_owned());
     vars.insert("user_arch".to_owned(), user_info.arch.to_owned());
     vars.insert("user_shell".to_owned(), user_info.shell.to_owned());
-    let system_message = if send_pane {
-        templates.render("SYSTEM_PROMPT_WITH_PANE", &vars).unwrap()
-    } else {
-        templates
-            .render("SYSTEM_PROMPT_WITHOUT_PANE", &vars)
-            .unwrap()
+    // non-empty turns on the nushell syntax instructions of the system prompt
+    let nushell = Shell::from_path(&user_info.shell) == Some(Shell::Nu);
+    vars.insert(
+        "nushell".to_owned(),
+        if nushell { "true" } else { "" }.to_owned(),
+    );
+    let build_messages = |vars: &std::collections::HashMap<String, String>,
+                          exchanges: &[session::Exchange]| {
+        let (system_template, user_template) = if send_pane {
+            ("SYSTEM_PROMPT_WITH_PANE", "USER_PROMPT_WITH_PANE")
+        } else {
+            ("SYSTEM_PROMPT_WITHOUT_PANE", "USER_PROMPT_WITHOUT_PANE")
+        };
+        let mut messages = vec![ChatMessage::system(
+            templates.render(system_template, vars).unwrap(),
+        )];
+        messages.extend(session::history_messages(exchanges));
+        messages.push(ChatMessage::user(
+            templates.render(user_template, vars).unwrap(),
+        ));
+        messages
     };
-    let user_input = if send_pane {
-        templates.render("USER_PROMPT_WITH_PANE", &vars).unwrap()
-    } else {
-        templates.render("USER_PROMPT_WITHOUT_PANE", &vars).unwrap()
+    let mut attempts = vec![fit_request(
+        config,
+        context_window,
+        &vars,
+        &exchanges,
+        build_messages,
+        debug_mode,
+    )];
+
+    let budget = match get_budget_config(&config_file.budget) {
+        Ok(budget) => budget,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    let ledger = usage::Ledger::open_default();
+    if let (Some(monthly), Some(ledger)) = (budget.monthly, &ledger) {
+        let month = &usage::date(session::now())[..7];
+        let spent = usage::month_cost(&ledger.load().unwrap_or_default(), month);
+        if spent >= monthly {
+            match budget.action.unwrap_or_default() {
+                BudgetAction::Warn => eprintln!(
+                    "*** Note: ${:.2} of the ${:.2} monthly budget is spent. See `ask-sh usage`. ***",
+                    spent, monthly
+                ),
+                BudgetAction::Refuse => {
+                    eprintln!(
+                        "The monthly budget of ${:.2} is spent (${:.2}). See `ask-sh usage`.",
+                        monthly, spent
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+    }
+    // profiles tried in order when the provider fails
+    let fallback_names: Vec<String> = match env::var(ENV_FALLBACK) {
+        Ok(names) => names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect(),
+        Err(_) => profile.fallback.clone().unwrap_or_default(),
     };
+    for name in &fallback_names {
+        match config_file
+            .profile(Some(name))
+            .and_then(|fallback| get_fallback_config(&fallback))
+        {
+            // the request is fitted again, as the fallback may have a smaller window
+            Ok(fallback) => {
+                let context_window = budget::context_window(
+                    &fallback.provider,
+                    &fallback.model,
+                    fallback.num_ctx,
+                    &config_file.context_windows,
+                );
+                attempts.push(fit_request(
+                    fallback,
+                    context_window,
+                    &vars,
+                    &exchanges,
+                    build_messages,
+                    debug_mode,
+                ));
+            }
+            Err(e) => eprintln!("*** Note: Fallback profile {} is skipped: {} ***", name, e),
+        }
+    }
+    let retry_policy = match get_env_or(ENV_MAX_RETRIES, profile.max_retries) {
+        Ok(max_retries) => RetryPolicy {
+            max_retries: max_retries.unwrap_or(retry::DEFAULT_MAX_RETRIES),
+            ..Default::default()
+        },
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    };
+    if debug_mode {
+        eprintln!("fallback: {}", fallback_names.join(", "));
+        eprintln!("max_retries: {}", retry_policy.max_retries);
+    }
 
-    let response = chat(user_input, system_message, &debug_mode);
+    let record_path = env::var(ENV_RECORD).ok();
+    let response = chat(
+        &attempts,
+        record_path.as_deref(),
+        format,
+        &retry_policy,
+        &debug_mode,
+    );
 
-    let response = match response {
+    let chat_response = match response {
         Ok(val) => val,
         Err(e) => {
             eprintln!("Communication with LLM provider failed: {}", e);
+            output::print_failure(format, &e.to_string());
             process::exit(1);
         }
     };
+    let response = chat_response.text.clone();
+    // a fallback may have answered
+    let provider_name = chat_response.provider.clone();
+    let model = chat_response.model.clone();
+    let attempt = &attempts[chat_response.attempt];
+    let estimator = &attempt.estimator;
+    let prompt_tokens = estimator.messages(&attempt.messages);
+    let user_prompt = attempt.messages.last().unwrap().content.clone();
+
+    if let Some(ledger) = &ledger {
+        // estimate what the provider did not report
+        let reported = chat_response.usage;
+        let record = usage::Record::new(
+            &provider_name,
+            &model,
+            reported.input_tokens.unwrap_or(prompt_tokens as u32),
+            reported
+                .output_tokens
+                .unwrap_or_else(|| estimator.tokens(&response) as u32),
+            reported.input_tokens.is_none() || reported.output_tokens.is_none(),
+            usage::price(&provider_name, &model, &config_file.prices),
+        );
+        if debug_mode {
+            eprintln!("cost: {:?}", record.cost);
+        }
+        if let Err(e) = ledger.append(&record) {
+            eprintln!("Could not record usage: {}", e);
+        }
+    }
 
-    let commands = post_process(&response);
+    let mut commands = extract::extract_commands(&response);
+
+    // blocked commands are neither offered nor kept in the session, so --continue does not
+    // show them to the model again
+    let mut session_response = response.clone();
+    commands.retain(|command| match safety::analyze(&command.text).first() {
+        Some(finding) if danger_policy.blocks(finding) => {
+            eprintln!(
+                "\n*** Warning: `{}` is not offered because it {} ({}). ***",
+                command.text.lines().next().unwrap_or_default(),
+                finding.reason,
+                finding.severity
+            );
+            session_response = session_response.replace(
+                &command.text,
+                &format!("[command removed: it {}]", finding.reason),
+            );
+            false
+        }
+        _ => true,
+    });
+
+    if let Some(store) = &session_store {
+        let commands: Vec<String> = commands.iter().map(|c| c.text.clone()).collect();
+        let exchange = session::Exchange::new(
+            &provider_name,
+            &model,
+            &user_input_without_flags,
+            &user_prompt,
+            &session_response,
+            &commands,
+        );
+        if let Err(e) = store.append(&session_name, &exchange) {
+            eprintln!("Could not save session {}: {}", session_name, e);
+        }
+    }
+
+    if format != OutputFormat::Text {
+        let mut answer = AnswerOutput::new(
+            &provider_name,
+            &model,
+            &response,
+            chat_response.stop_reason.as_ref(),
+            chat_response.usage,
+            &commands,
+        );
+        answer.errors = chat_response.errors;
+        if no_suggest {
+            answer.commands.clear();
+        }
+        match format {
+            OutputFormat::Ndjson => StreamLine::Result(&answer).print(),
+            _ => answer.print(),
+        }
+        return;
+    }
 
     // print suggested commands to stdout to further process
+    // one per line, so scripts that cannot be joined into a line are left out
     if !no_suggest {
-        for command in commands {
-            println!("{}", command);
+        let mut skipped = 0;
+        for command in &commands {
+            let line = match command.one_line() {
+                Some(line) => line,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            // `ask-sh pick` shows the warning and the explanation next to the command
+            let finding = safety::analyze(&command.text).into_iter().next();
+            let suggestion = picker::Suggestion {
+                command: line,
+                warning: finding
+                    .as_ref()
+                    .map(|finding| format!("⚠ {}: {}", finding.severity, finding.reason)),
+                // checked again by the picker, as the command may be edited
+                confirm: danger_policy == DangerPolicy::Confirm,
+                explanation: command.explanation.clone(),
+            };
+            println!("{}", suggestion.to_line());
+        }
+        if skipped > 0 {
+            eprintln!("\n*** Note: {} multi-line script(s) with a heredoc cannot be typed into your terminal. Copy them from the answer above. ***", skipped);
         }
     }
 }
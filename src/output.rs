@@ -0,0 +1,189 @@
+//! Machine readable output of `--format json` and `--format ndjson`
+
+use serde::Serialize;
+use std::str::FromStr;
+
+use crate::extract::SuggestedCommand;
+use crate::llm::{StopReason, StreamError, Usage};
+use crate::safety::{self, Finding};
+
+/// How the answer is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Prose on stderr, one command per line on stdout for the `ask` shell function
+    Text,
+    /// A single JSON object on stdout once the answer is complete
+    Json,
+    /// One JSON object per line on stdout while the answer streams, then the result
+    Ndjson,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            other => Err(format!(
+                "Unknown format: {} (expected text, json or ndjson)",
+                other
+            )),
+        }
+    }
+}
+
+/// A suggested command, as written to JSON
+#[derive(Debug, Serialize)]
+pub struct CommandOutput {
+    /// The command as typed, possibly spanning several lines
+    pub command: String,
+    /// Single line form, `null` for scripts that cannot be joined
+    pub one_line: Option<String>,
+    pub language: Option<String>,
+    pub explanation: String,
+    /// Destructive patterns found in the command, most severe first
+    pub danger: Vec<Finding>,
+}
+
+impl From<&SuggestedCommand> for CommandOutput {
+    fn from(command: &SuggestedCommand) -> Self {
+        Self {
+            command: command.text.clone(),
+            one_line: command.one_line(),
+            language: command.language.clone(),
+            explanation: command.explanation.clone(),
+            danger: safety::analyze(&command.text),
+        }
+    }
+}
+
+/// The complete answer
+#[derive(Debug, Serialize)]
+pub struct AnswerOutput {
+    pub provider: String,
+    pub model: String,
+    pub answer: String,
+    pub stop_reason: Option<String>,
+    pub usage: Usage,
+    pub commands: Vec<CommandOutput>,
+    /// Errors reported while the answer streamed
+    pub errors: Vec<StreamError>,
+}
+
+impl AnswerOutput {
+    pub fn new(
+        provider: &str,
+        model: &str,
+        answer: &str,
+        stop_reason: Option<&StopReason>,
+        usage: Usage,
+        commands: &[SuggestedCommand],
+    ) -> Self {
+        Self {
+            provider: provider.to_string(),
+            model: model.to_string(),
+            answer: answer.to_string(),
+            stop_reason: stop_reason.map(|reason| reason.as_str().to_string()),
+            usage,
+            commands: commands.iter().map(CommandOutput::from).collect(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("{}", serde_json::to_string(self).unwrap());
+    }
+}
+
+/// One line of `--format ndjson`
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum StreamLine<'a> {
+    Text {
+        text: &'a str,
+    },
+    Stop {
+        reason: &'a str,
+    },
+    Usage(Usage),
+    Error {
+        kind: &'a str,
+        message: &'a str,
+    },
+    /// The complete answer, always the last line
+    Result(&'a AnswerOutput),
+}
+
+impl<'a> StreamLine<'a> {
+    pub fn error(error: &'a StreamError) -> Self {
+        StreamLine::Error {
+            kind: &error.kind,
+            message: &error.message,
+        }
+    }
+
+    pub fn print(&self) {
+        println!("{}", serde_json::to_string(self).unwrap());
+    }
+}
+
+/// Report a request that failed before any answer. The JSON formats still write JSON to
+/// stdout, so scripts reading it do not get empty input.
+pub fn print_failure(format: OutputFormat, message: &str) {
+    let error = StreamError {
+        kind: "request".to_string(),
+        message: message.to_string(),
+    };
+    match format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => println!("{}", serde_json::json!({ "error": error })),
+        OutputFormat::Ndjson => StreamLine::error(&error).print(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extract::extract_commands;
+
+    #[test]
+    fn test_answer_json() {
+        let text = "List files:\n```bash\nls -la\n```";
+        let answer = AnswerOutput::new(
+            "openai",
+            "gpt-4o",
+            text,
+            Some(&StopReason::EndTurn),
+            Usage {
+                input_tokens: Some(10),
+                output_tokens: None,
+            },
+            &extract_commands(text),
+        );
+        let json = serde_json::to_value(&answer).unwrap();
+        assert_eq!(json["stop_reason"], "end_turn");
+        assert_eq!(json["usage"]["input_tokens"], 10);
+        assert_eq!(json["commands"][0]["command"], "ls -la");
+        assert_eq!(json["commands"][0]["language"], "bash");
+        assert_eq!(json["commands"][0]["explanation"], "List files:");
+        assert_eq!(json["commands"][0]["danger"], serde_json::json!([]));
+
+        let line = serde_json::to_value(StreamLine::Result(&answer)).unwrap();
+        assert_eq!(line["type"], "result");
+        assert_eq!(line["model"], "gpt-4o");
+    }
+
+    #[test]
+    fn test_stream_lines() {
+        let line = serde_json::to_string(&StreamLine::Text { text: "hi" }).unwrap();
+        assert_eq!(line, r#"{"type":"text","text":"hi"}"#);
+        let line = serde_json::to_string(&StreamLine::Usage(Usage::default())).unwrap();
+        assert_eq!(
+            line,
+            r#"{"type":"usage","input_tokens":null,"output_tokens":null}"#
+        );
+        assert!("yaml".parse::<OutputFormat>().is_err());
+    }
+}
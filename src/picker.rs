@@ -0,0 +1,561 @@
+//! Interactive selector for the suggested commands: `ask-sh pick`.
+//!
+//! Reads the lines printed by `ask-sh` from stdin, draws on stderr and reads keys from the
+//! terminal, then prints the chosen command on stdout.
+
+use crossterm::{
+    cursor::{MoveToColumn, MoveUp},
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+};
+use std::io::{self, BufRead, Write};
+
+use crate::safety;
+
+/// Rows of suggestions shown at once
+const MAX_ROWS: usize = 10;
+
+const PROMPT: &str = "AI suggested commands (Enter to use, Tab to edit, Esc to exit)> ";
+const EDIT_PROMPT: &str = "Edit> ";
+
+/// A suggested command, one per line between `ask-sh` and `ask-sh pick`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Suggestion {
+    pub command: String,
+    /// e.g. `⚠ critical: recursively deletes files`
+    pub warning: Option<String>,
+    /// Dangerous commands have to be confirmed before they are used. The command is checked
+    /// again once chosen, as it may have been edited.
+    pub confirm: bool,
+    pub explanation: String,
+}
+
+const CONFIRM_MARKER: &str = " [confirm]";
+
+impl Suggestion {
+    /// Parse `command<TAB>warning<TAB>explanation`. Trailing fields are optional.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split('\t');
+        let command = fields.next()?.trim().to_string();
+        if command.is_empty() {
+            return None;
+        }
+        let warning = fields.next().unwrap_or_default();
+        let (warning, confirm) = match warning.strip_suffix(CONFIRM_MARKER) {
+            Some(warning) => (warning, true),
+            None => (warning, false),
+        };
+        Some(Self {
+            command,
+            warning: Some(warning.to_string()).filter(|warning| !warning.is_empty()),
+            confirm,
+            explanation: fields.next().unwrap_or_default().to_string(),
+        })
+    }
+
+    pub fn to_line(&self) -> String {
+        if self.warning.is_none() && !self.confirm && self.explanation.is_empty() {
+            return self.command.clone();
+        }
+        format!(
+            "{}\t{}{}\t{}",
+            self.command,
+            self.warning.as_deref().unwrap_or_default(),
+            if self.confirm { CONFIRM_MARKER } else { "" },
+            self.explanation.replace('\t', " ")
+        )
+    }
+}
+
+/// Position of the characters of `query` in order in `text`, ignoring case.
+/// Returns the length of the matched span, shorter being better.
+fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let mut position = 0;
+    let mut first = None;
+    for q in query.to_lowercase().chars().filter(|c| !c.is_whitespace()) {
+        let found = text[position..].iter().position(|c| *c == q)? + position;
+        first.get_or_insert(found);
+        position = found + 1;
+    }
+    Some(position - first.unwrap_or(0))
+}
+
+fn byte_index(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map(|(i, _)| i)
+        .unwrap_or(text.len())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Mode {
+    Browse,
+    /// Editing the command before using it. `cursor` counts characters.
+    Edit {
+        buffer: String,
+        cursor: usize,
+    },
+    /// Waiting for `yes` before using a dangerous command
+    Confirm {
+        command: String,
+        /// e.g. `⚠ critical: recursively deletes files`
+        warning: String,
+        buffer: String,
+    },
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Action {
+    Continue,
+    Accept(String),
+    Cancel,
+}
+
+pub struct Picker {
+    suggestions: Vec<Suggestion>,
+    query: String,
+    /// Indices of the suggestions matching `query`, best first
+    matches: Vec<usize>,
+    selected: usize,
+    mode: Mode,
+}
+
+impl Picker {
+    pub fn new(suggestions: Vec<Suggestion>) -> Self {
+        let mut picker = Self {
+            suggestions,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            mode: Mode::Browse,
+        };
+        picker.refilter();
+        picker
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(usize, usize)> = self
+            .suggestions
+            .iter()
+            .enumerate()
+            .filter_map(|(i, suggestion)| {
+                fuzzy_score(&self.query, &suggestion.command).map(|score| (score, i))
+            })
+            .collect();
+        scored.sort();
+        self.matches = scored.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+    }
+
+    fn current(&self) -> Option<&Suggestion> {
+        self.matches
+            .get(self.selected)
+            .map(|i| &self.suggestions[*i])
+    }
+
+    /// Use `command`, unless it is dangerous and the selected suggestion needs a confirmation.
+    /// The final text is checked, so edits cannot skip the confirmation.
+    fn accept(&mut self, command: String) -> Action {
+        if self.current().is_some_and(|suggestion| suggestion.confirm) {
+            if let Some(finding) = safety::analyze(&command).first() {
+                self.mode = Mode::Confirm {
+                    warning: format!("⚠ {}: {}", finding.severity, finding.reason),
+                    command,
+                    buffer: String::new(),
+                };
+                return Action::Continue;
+            }
+        }
+        Action::Accept(command)
+    }
+
+    pub fn handle(&mut self, key: KeyEvent) -> Action {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        if ctrl && key.code == KeyCode::Char('c') {
+            return Action::Cancel;
+        }
+
+        match &mut self.mode {
+            Mode::Browse => match key.code {
+                KeyCode::Esc => return Action::Cancel,
+                KeyCode::Char('g') if ctrl => return Action::Cancel,
+                KeyCode::Up => self.selected = self.selected.saturating_sub(1),
+                KeyCode::Char('p') | KeyCode::Char('k') if ctrl => {
+                    self.selected = self.selected.saturating_sub(1)
+                }
+                KeyCode::Down if self.selected + 1 < self.matches.len() => self.selected += 1,
+                KeyCode::Char('n') | KeyCode::Char('j')
+                    if ctrl && self.selected + 1 < self.matches.len() =>
+                {
+                    self.selected += 1
+                }
+                KeyCode::Enter => {
+                    if let Some(command) = self.current().map(|s| s.command.clone()) {
+                        return self.accept(command);
+                    }
+                }
+                KeyCode::Tab | KeyCode::Right => {
+                    if let Some(command) = self.current().map(|s| s.command.clone()) {
+                        self.mode = Mode::Edit {
+                            cursor: command.chars().count(),
+                            buffer: command,
+                        };
+                    }
+                }
+                KeyCode::Char('e') if ctrl => {
+                    if let Some(command) = self.current().map(|s| s.command.clone()) {
+                        self.mode = Mode::Edit {
+                            cursor: command.chars().count(),
+                            buffer: command,
+                        };
+                    }
+                }
+                KeyCode::Backspace => {
+                    self.query.pop();
+                    self.refilter();
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    self.query.push(c);
+                    self.refilter();
+                }
+                _ => {}
+            },
+            Mode::Edit { buffer, cursor } => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Enter => {
+                    let command = buffer.trim().to_string();
+                    if !command.is_empty() {
+                        return self.accept(command);
+                    }
+                }
+                KeyCode::Left => *cursor = cursor.saturating_sub(1),
+                KeyCode::Right => *cursor = (*cursor + 1).min(buffer.chars().count()),
+                KeyCode::Home => *cursor = 0,
+                KeyCode::Char('a') if ctrl => *cursor = 0,
+                KeyCode::End => *cursor = buffer.chars().count(),
+                KeyCode::Char('e') if ctrl => *cursor = buffer.chars().count(),
+                KeyCode::Backspace if *cursor > 0 => {
+                    *cursor -= 1;
+                    buffer.remove(byte_index(buffer, *cursor));
+                }
+                KeyCode::Delete if *cursor < buffer.chars().count() => {
+                    buffer.remove(byte_index(buffer, *cursor));
+                }
+                KeyCode::Char(c) if !ctrl => {
+                    buffer.insert(byte_index(buffer, *cursor), c);
+                    *cursor += 1;
+                }
+                _ => {}
+            },
+            Mode::Confirm {
+                command, buffer, ..
+            } => match key.code {
+                KeyCode::Esc => self.mode = Mode::Browse,
+                KeyCode::Enter => {
+                    if buffer == "yes" {
+                        return Action::Accept(command.clone());
+                    }
+                    self.mode = Mode::Browse;
+                }
+                KeyCode::Backspace => {
+                    buffer.pop();
+                }
+                KeyCode::Char(c) if !ctrl => buffer.push(c),
+                _ => {}
+            },
+        }
+        Action::Continue
+    }
+
+    /// Draw the prompt line, the suggestions and the explanation below the cursor,
+    /// leaving the cursor on the prompt line
+    fn render(&self, out: &mut impl Write, width: usize) -> io::Result<()> {
+        let fit = |text: &str, used: usize| -> String {
+            text.chars().take(width.saturating_sub(used + 1)).collect()
+        };
+        queue!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+
+        let (prompt, input, cursor) = match &self.mode {
+            Mode::Browse => (
+                PROMPT.to_string(),
+                self.query.clone(),
+                self.query.chars().count(),
+            ),
+            Mode::Edit { buffer, cursor } => (EDIT_PROMPT.to_string(), buffer.clone(), *cursor),
+            Mode::Confirm {
+                warning, buffer, ..
+            } => (
+                format!("{}. Type yes to use it anyway: ", warning),
+                buffer.clone(),
+                buffer.chars().count(),
+            ),
+        };
+        let prompt = fit(&prompt, 0);
+        queue!(
+            out,
+            Print(&prompt),
+            Print(fit(&input, prompt.chars().count()))
+        )?;
+
+        let mut lines = 0;
+        // keep the selection in the visible window
+        let start = (self.selected + 1).saturating_sub(MAX_ROWS);
+        for (row, index) in self.matches.iter().enumerate().skip(start).take(MAX_ROWS) {
+            let suggestion = &self.suggestions[*index];
+            let marker = if row == self.selected { "> " } else { "  " };
+            queue!(out, Print("\r\n"))?;
+            lines += 1;
+            if row == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            let command = fit(&format!("{}{}", marker, suggestion.command), 0);
+            queue!(out, Print(&command), SetAttribute(Attribute::Reset))?;
+            if let Some(warning) = &suggestion.warning {
+                let color = if warning.contains("critical") {
+                    Color::Red
+                } else {
+                    Color::Yellow
+                };
+                queue!(
+                    out,
+                    SetForegroundColor(color),
+                    Print(fit(&format!("  {}", warning), command.chars().count())),
+                    ResetColor
+                )?;
+            }
+        }
+        if self.matches.is_empty() {
+            queue!(out, Print("\r\n  (no match)"))?;
+            lines += 1;
+        }
+        if let Some(suggestion) = self.current() {
+            if !suggestion.explanation.is_empty() {
+                queue!(
+                    out,
+                    Print("\r\n"),
+                    SetAttribute(Attribute::Dim),
+                    Print(fit(&suggestion.explanation, 0)),
+                    SetAttribute(Attribute::Reset)
+                )?;
+                lines += 1;
+            }
+        }
+
+        if lines > 0 {
+            queue!(out, MoveUp(lines as u16))?;
+        }
+        let column = (prompt.chars().count() + cursor).min(width.saturating_sub(1));
+        queue!(out, MoveToColumn(column as u16))?;
+        out.flush()
+    }
+}
+
+/// Raw mode of the terminal, disabled again when dropped so errors cannot leave it on
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn pick(suggestions: Vec<Suggestion>) -> io::Result<Option<String>> {
+    let mut picker = Picker::new(suggestions);
+    let mut out = io::stderr();
+
+    // keys come from the terminal even though stdin is a pipe
+    let raw_mode = RawMode::enable()?;
+    let result = (|| loop {
+        let width = match terminal::size() {
+            Ok((width, _)) if width > 0 => width as usize,
+            _ => 80,
+        };
+        picker.render(&mut out, width)?;
+        if let Event::Key(key) = event::read()? {
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match picker.handle(key) {
+                Action::Continue => {}
+                Action::Accept(command) => return Ok(Some(command)),
+                Action::Cancel => return Ok(None),
+            }
+        }
+    })();
+    queue!(out, MoveToColumn(0), Clear(ClearType::FromCursorDown))?;
+    out.flush()?;
+    drop(raw_mode);
+    result
+}
+
+/// Entry point of `ask-sh pick`. Returns the exit status: 0 when a command was chosen.
+pub fn run() -> i32 {
+    let suggestions: Vec<Suggestion> = io::stdin()
+        .lock()
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| Suggestion::parse(&line))
+        .collect();
+    if suggestions.is_empty() {
+        return 1;
+    }
+    match pick(suggestions) {
+        Ok(Some(command)) => {
+            println!("{}", command);
+            0
+        }
+        Ok(None) => 1,
+        Err(e) => {
+            eprintln!("ask-sh pick: {}", e);
+            2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_text(picker: &mut Picker, text: &str) {
+        for c in text.chars() {
+            assert_eq!(picker.handle(key(KeyCode::Char(c))), Action::Continue);
+        }
+    }
+
+    fn suggestions() -> Vec<Suggestion> {
+        [
+            "ls -la\t [confirm]\t",
+            "git status",
+            "rm -rf build\t⚠ warning: recursively deletes files [confirm]\tClean up",
+        ]
+        .iter()
+        .map(|line| Suggestion::parse(line).unwrap())
+        .collect()
+    }
+
+    #[test]
+    fn test_suggestion_lines() {
+        let suggestion = &suggestions()[2];
+        assert_eq!(suggestion.command, "rm -rf build");
+        assert_eq!(
+            suggestion.warning.as_deref(),
+            Some("⚠ warning: recursively deletes files")
+        );
+        assert!(suggestion.confirm);
+        assert_eq!(suggestion.explanation, "Clean up");
+        assert_eq!(
+            Suggestion::parse(&suggestion.to_line()).as_ref(),
+            Some(suggestion)
+        );
+        assert_eq!(suggestions()[1].to_line(), "git status");
+        assert_eq!(
+            Suggestion::parse(&suggestions()[0].to_line()),
+            Some(suggestions()[0].clone())
+        );
+        assert_eq!(Suggestion::parse("  "), None);
+    }
+
+    #[test]
+    fn test_fuzzy_filter_and_navigation() {
+        let mut picker = Picker::new(suggestions());
+        type_text(&mut picker, "gst");
+        assert_eq!(picker.matches, vec![1]);
+        picker.handle(key(KeyCode::Backspace));
+        picker.handle(key(KeyCode::Backspace));
+        picker.handle(key(KeyCode::Backspace));
+        assert_eq!(picker.matches.len(), 3);
+
+        picker.handle(key(KeyCode::Down));
+        assert_eq!(
+            picker.handle(key(KeyCode::Enter)),
+            Action::Accept("git status".to_string())
+        );
+        assert_eq!(picker.handle(key(KeyCode::Esc)), Action::Cancel);
+    }
+
+    #[test]
+    fn test_edit_before_accept() {
+        let mut picker = Picker::new(suggestions());
+        picker.handle(key(KeyCode::Tab));
+        picker.handle(key(KeyCode::Home));
+        type_text(&mut picker, "sudo ");
+        picker.handle(key(KeyCode::End));
+        picker.handle(key(KeyCode::Backspace));
+        type_text(&mut picker, "ah");
+        assert_eq!(
+            picker.handle(key(KeyCode::Enter)),
+            Action::Accept("sudo ls -lah".to_string())
+        );
+    }
+
+    #[test]
+    fn test_dangerous_commands_need_confirmation() {
+        let mut picker = Picker::new(suggestions());
+        type_text(&mut picker, "rm");
+        assert_eq!(picker.handle(key(KeyCode::Enter)), Action::Continue);
+        type_text(&mut picker, "no");
+        assert_eq!(picker.handle(key(KeyCode::Enter)), Action::Continue);
+        assert_eq!(picker.mode, Mode::Browse);
+
+        picker.handle(key(KeyCode::Enter));
+        type_text(&mut picker, "yes");
+        assert_eq!(
+            picker.handle(key(KeyCode::Enter)),
+            Action::Accept("rm -rf build".to_string())
+        );
+    }
+
+    #[test]
+    fn test_edited_commands_are_checked_again() {
+        // edited into a dangerous command: confirmed with the new warning
+        let mut picker = Picker::new(suggestions());
+        picker.handle(key(KeyCode::Tab));
+        for _ in 0.."ls -la".len() {
+            picker.handle(key(KeyCode::Backspace));
+        }
+        type_text(&mut picker, "rm -rf ~");
+        assert_eq!(picker.handle(key(KeyCode::Enter)), Action::Continue);
+        assert!(matches!(
+            &picker.mode,
+            Mode::Confirm { warning, .. } if warning.starts_with("⚠ critical")
+        ));
+
+        // edited into a harmless one: used right away
+        let mut picker = Picker::new(suggestions());
+        type_text(&mut picker, "rm");
+        picker.handle(key(KeyCode::Tab));
+        for _ in 0.."-rf build".len() {
+            picker.handle(key(KeyCode::Backspace));
+        }
+        type_text(&mut picker, "notes.txt");
+        assert_eq!(
+            picker.handle(key(KeyCode::Enter)),
+            Action::Accept("rm notes.txt".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render_leaves_cursor_on_prompt() {
+        let mut picker = Picker::new(suggestions());
+        type_text(&mut picker, "r");
+        let mut out = Vec::new();
+        picker.render(&mut out, 80).unwrap();
+        let screen = String::from_utf8_lossy(&out);
+        assert!(screen.contains("> rm -rf build"));
+        assert!(screen.contains("Clean up"));
+    }
+}
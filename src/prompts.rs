@@ -47,11 +47,27 @@ ffmpeg -i input.mp4 -c:v libx264 -crf 23 -c:a aac -b:a 128k -ac 2 -ar 44100 outp
 - *** AVOID `awk` OR `sed` AS MUCH AS POSSIBLE. Instead, installing other commands is allowed. ***
 
 Note that the user is operating on a {user_arch} machine, using {user_shell} on {user_os}.
+{{ if nushell }}
+{{ call NUSHELL_INSTRUCTIONS with nushell }}
+{{ endif }}
 "#;
 
+/// Added to both system prompts for nushell users
+const NUSHELL_INSTRUCTIONS: &str = r#"The user's shell is nushell, which is not a POSIX shell. Write every command in nushell syntax:
+- Use nushell commands and structured pipelines (`ls | where size > 1mb`, `open data.json | get items`) instead of POSIX pipelines with `grep`, `cut` or `xargs`.
+- Use `;` instead of `&&`, `(command)` instead of `$(command)`, and `$env.NAME = "value"` instead of `export NAME=value`.
+- Prefix external commands that share a name with a nushell command with `^`, e.g. `^ls`."#;
+
 const USER_PROMPT_WITH_PANE: &str = r#"
 Terminal state:
 {pane_text}
+{{ if history }}
+Recently run commands (oldest first):
+{history}
+{{ endif }}
+{{ if last_command }}
+Last command: {last_command}
+{{ endif }}
 User's request:
 {user_input}
 "#;
@@ -69,19 +85,103 @@ ffmpeg -i input.mp4 -c:v libx264 -crf 23 -c:a aac -b:a 128k -ac 2 -ar 44100 outp
 - *** AVOID `awk` OR `sed` AS MUCH AS POSSIBLE. Instead, installing other commands is allowed. ***
 
 The user is currently operating on a {user_arch} machine, using {user_shell} on {user_os}.
+{{ if nushell }}
+{{ call NUSHELL_INSTRUCTIONS with nushell }}
+{{ endif }}
 "#;
 
 const USER_PROMPT_WITHOUT_PANE: &str = r#"
+{{ if history }}
+Recently run commands (oldest first):
+{history}
+{{ endif }}
+{{ if last_command }}
+Last command: {last_command}
+{{ endif }}
 User's request: {user_input}
 "#;
 
 pub fn get_template() -> TinyTemplate<'static> {
     let mut templates = TinyTemplate::new();
+    // values are terminal text and shell commands, not HTML: `&&` or `<` are sent as typed
+    templates.set_default_formatter(&tinytemplate::format_unescaped);
+
+    // called by the system prompts, so custom ones can use it too
+    templates
+        .add_template("NUSHELL_INSTRUCTIONS", NUSHELL_INSTRUCTIONS)
+        .unwrap();
 
     // Add templates from static PROMPTS
     for (name, content) in PROMPTS.iter() {
-        templates.add_template(name, &content).unwrap();
+        templates.add_template(name, content).unwrap();
     }
 
     templates
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn render_system_prompt(name: &str, shell: &str, nushell: &str) -> String {
+        let mut vars = HashMap::new();
+        vars.insert("user_arch", "x86_64");
+        vars.insert("user_os", "linux");
+        vars.insert("user_shell", shell);
+        vars.insert("nushell", nushell);
+        get_template().render(name, &vars).unwrap()
+    }
+
+    #[test]
+    fn test_nushell_instructions() {
+        for name in ["SYSTEM_PROMPT_WITH_PANE", "SYSTEM_PROMPT_WITHOUT_PANE"] {
+            let prompt = render_system_prompt(name, "/usr/bin/nu", "true");
+            assert!(prompt.contains(&format!("\n{}\n", NUSHELL_INSTRUCTIONS)));
+            let prompt = render_system_prompt(name, "/bin/zsh", "");
+            assert!(!prompt.contains("nushell"));
+            assert!(prompt.contains("using /bin/zsh on linux"));
+        }
+    }
+
+    #[test]
+    fn test_pane_text_and_input_are_not_escaped() {
+        let pane_text = "$ make && ./app < in.txt > out.log\n<stdin>: \"quoted\" & 'single'";
+        let user_input = "why does `a && b` print <none>?";
+        let mut vars = HashMap::new();
+        vars.insert("pane_text", pane_text);
+        vars.insert("user_input", user_input);
+        vars.insert("history", "");
+        vars.insert("last_command", "");
+        let prompt = get_template()
+            .render("USER_PROMPT_WITH_PANE", &vars)
+            .unwrap();
+        assert!(prompt.contains(&format!("Terminal state:\n{}\n", pane_text)));
+        assert!(prompt.contains(&format!("User's request:\n{}\n", user_input)));
+    }
+
+    #[test]
+    fn test_history_in_user_prompt() {
+        let mut vars = HashMap::new();
+        vars.insert("user_input", "why did that fail?");
+        vars.insert("last_command", "");
+        vars.insert("history", "$ make && ./app > out.log  (exit status 2)");
+        let prompt = get_template()
+            .render("USER_PROMPT_WITHOUT_PANE", &vars)
+            .unwrap();
+        assert!(prompt.contains(
+            "Recently run commands (oldest first):\n$ make && ./app > out.log  (exit status 2)"
+        ));
+        vars.insert("history", "");
+        let prompt = get_template()
+            .render("USER_PROMPT_WITHOUT_PANE", &vars)
+            .unwrap();
+        assert!(!prompt.contains("Recently run commands"));
+        assert!(!prompt.contains("Last command:"));
+        vars.insert("last_command", "`make` exited 2 after 14s in /src");
+        let prompt = get_template()
+            .render("USER_PROMPT_WITHOUT_PANE", &vars)
+            .unwrap();
+        assert!(prompt.contains("Last command: `make` exited 2 after 14s in /src"));
+    }
+}
@@ -0,0 +1,286 @@
+//! Rule based detection of destructive commands before they are offered to the user
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// How bad a flagged command is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Destroys data that can usually be recovered, or runs unreviewed code
+    Warning,
+    /// Can wipe a disk, a filesystem or a database, or hang the machine
+    Critical,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Critical => write!(f, "critical"),
+        }
+    }
+}
+
+/// A rule matched by a command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub rule: &'static str,
+    pub severity: Severity,
+    pub reason: &'static str,
+}
+
+/// What to do with flagged commands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DangerPolicy {
+    /// Offer them with the warning shown in the selector
+    #[default]
+    Warn,
+    /// Offer them, but ask for confirmation once selected
+    Confirm,
+    /// Do not offer critical ones; warnings are offered with the warning shown
+    Block,
+}
+
+impl DangerPolicy {
+    /// Whether a command with `finding` is kept from the user
+    pub fn blocks(&self, finding: &Finding) -> bool {
+        *self == DangerPolicy::Block && finding.severity == Severity::Critical
+    }
+}
+
+impl FromStr for DangerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(DangerPolicy::Warn),
+            "confirm" => Ok(DangerPolicy::Confirm),
+            "block" => Ok(DangerPolicy::Block),
+            other => Err(format!("Unknown danger policy: {}", other)),
+        }
+    }
+}
+
+struct Rule {
+    name: &'static str,
+    severity: Severity,
+    pattern: Regex,
+    reason: &'static str,
+}
+
+fn rule(name: &'static str, severity: Severity, pattern: &str, reason: &'static str) -> Rule {
+    Rule {
+        name,
+        severity,
+        pattern: Regex::new(pattern).unwrap(),
+        reason,
+    }
+}
+
+/// `rm` with a recursive flag, e.g. `-rf`, `-R` or `--recursive`
+const RM_RECURSIVE: &str =
+    r"\brm\s+(?:-\S+\s+)*?(?:-[a-zA-Z]*[rR][a-zA-Z]*|--recursive)\s+(?:-\S+\s+)*";
+
+/// Disk devices under /dev; `/dev/null` and friends are fine to write to
+const DISK_DEVICE: &str = r"/dev/(?:sd|nvme|hd|vd|xvd|mmcblk|disk|rdisk|mapper/|md)";
+
+static RULES: Lazy<Vec<Rule>> = Lazy::new(|| {
+    vec![
+        rule(
+            "rm-root",
+            Severity::Critical,
+            &format!(
+                r#"{}["']?(?:/|/\*|~/?|\$HOME/?|\*|\.\.?/?)["']?(?:\s|;|&|\||$)"#,
+                RM_RECURSIVE
+            ),
+            "recursively deletes the root, home or current directory",
+        ),
+        rule(
+            "rm-no-preserve-root",
+            Severity::Critical,
+            r"\brm\b[^;&|\n]*--no-preserve-root",
+            "disables the protection against deleting /",
+        ),
+        rule(
+            "rm-recursive",
+            Severity::Warning,
+            RM_RECURSIVE,
+            "recursively deletes files",
+        ),
+        rule(
+            "dd-device",
+            Severity::Critical,
+            &format!(r"\bdd\b[^;&|\n]*\bof={}", DISK_DEVICE),
+            "overwrites a disk device",
+        ),
+        rule(
+            "redirect-device",
+            Severity::Critical,
+            &format!(r">\s*{}", DISK_DEVICE),
+            "overwrites a disk device",
+        ),
+        rule(
+            "mkfs",
+            Severity::Critical,
+            r"\b(?:mkfs(?:\.\w+)?|wipefs)\b",
+            "formats a device, erasing its data",
+        ),
+        rule(
+            "recursive-root-permissions",
+            Severity::Critical,
+            r"\bch(?:mod|own|grp)\s+(?:\S+\s+)*?(?:-[a-zA-Z]*R[a-zA-Z]*|--recursive)\s+(?:\S+\s+)*?/(?:\s|;|&|\||$)",
+            "changes permissions of the whole filesystem",
+        ),
+        rule(
+            "chmod-777",
+            Severity::Warning,
+            r"\bchmod\s+(?:\S+\s+)*?(?:0?777|a\+rwx)\b",
+            "makes files writable by everyone",
+        ),
+        rule(
+            "pipe-to-shell",
+            Severity::Warning,
+            r"\b(?:curl|wget)\b[^;&|\n]*\|\s*(?:sudo\s+)?(?:ba|z|da|k|fi)?sh\b",
+            "runs a script from the internet without reviewing it",
+        ),
+        rule(
+            "shell-process-substitution",
+            Severity::Warning,
+            r"\b(?:ba|z)?sh\s+<\(\s*(?:curl|wget)\b",
+            "runs a script from the internet without reviewing it",
+        ),
+        rule(
+            "git-force-push",
+            Severity::Warning,
+            r"\bgit\s+push\b[^;&|\n]*(?:\s--force\b|\s-[a-zA-Z]*f[a-zA-Z]*\b|\s\+\S)",
+            "overwrites history on the remote",
+        ),
+        rule(
+            "git-discard",
+            Severity::Warning,
+            r"\bgit\s+(?:reset\s+[^;&|\n]*--hard|clean\s+[^;&|\n]*-[a-zA-Z]*f)",
+            "discards uncommitted changes",
+        ),
+        rule(
+            "sql-drop",
+            Severity::Critical,
+            r"(?i)\b(?:drop\s+(?:table|database|schema)|truncate\s+table)\b",
+            "permanently deletes database objects",
+        ),
+        rule(
+            "sql-delete-all",
+            Severity::Warning,
+            r#"(?i)\bdelete\s+from\s+[\w."`]+\s*(?:;|"|'|$)"#,
+            "deletes every row of the table (no WHERE clause)",
+        ),
+        rule(
+            "fork-bomb",
+            Severity::Critical,
+            r":\s*\(\s*\)\s*\{\s*:\s*\|\s*:\s*&\s*\}\s*;\s*:",
+            "fork bomb; exhausts the process table and hangs the machine",
+        ),
+    ]
+});
+
+/// Rules matched by `command`, most severe first
+pub fn analyze(command: &str) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = RULES
+        .iter()
+        .filter(|rule| rule.pattern.is_match(command))
+        .map(|rule| Finding {
+            rule: rule.name,
+            severity: rule.severity,
+            reason: rule.reason,
+        })
+        .collect();
+    findings.sort_by_key(|finding| std::cmp::Reverse(finding.severity));
+    findings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(command: &str) -> Vec<&'static str> {
+        analyze(command)
+            .iter()
+            .map(|finding| finding.rule)
+            .collect()
+    }
+
+    #[test]
+    fn test_destructive_commands_are_flagged() {
+        let cases = [
+            ("sudo rm -rf /", "rm-root"),
+            ("rm -fr ~", "rm-root"),
+            ("rm -r -f \"$HOME/\"", "rm-root"),
+            ("rm --no-preserve-root -rf /", "rm-no-preserve-root"),
+            ("rm -rf build", "rm-recursive"),
+            ("dd if=image.iso of=/dev/sdb bs=4M", "dd-device"),
+            ("sudo dd if=image.img of=/dev/rdisk2 bs=1m", "dd-device"),
+            ("cat image > /dev/nvme0n1", "redirect-device"),
+            ("mkfs.ext4 /dev/sdb1", "mkfs"),
+            ("chmod -R 777 /", "recursive-root-permissions"),
+            ("chmod 777 script.sh", "chmod-777"),
+            (
+                "curl -fsSL https://example.com/install.sh | sudo bash",
+                "pipe-to-shell",
+            ),
+            (
+                "bash <(curl -s https://example.com/x)",
+                "shell-process-substitution",
+            ),
+            ("git push --force origin main", "git-force-push"),
+            ("git push -f", "git-force-push"),
+            ("git push origin +main", "git-force-push"),
+            ("git reset --hard HEAD~1", "git-discard"),
+            ("psql -c 'DROP TABLE users;'", "sql-drop"),
+            ("mysql -e \"delete from users\"", "sql-delete-all"),
+            (":(){ :|:& };:", "fork-bomb"),
+        ];
+        for (command, rule) in cases {
+            assert!(
+                rules(command).contains(&rule),
+                "{} should match {}, got {:?}",
+                command,
+                rule,
+                rules(command)
+            );
+        }
+    }
+
+    #[test]
+    fn test_harmless_commands_pass() {
+        let commands = [
+            "ls -la /",
+            "rm file.txt",
+            "git push origin feature",
+            "git push --set-upstream origin main",
+            "dd if=/dev/zero of=disk.img bs=1M count=10",
+            "dd if=/dev/zero of=/dev/null bs=1M count=100",
+            "echo hi > /dev/null",
+            "chmod 755 script.sh",
+            "curl -O https://example.com/file.tar.gz",
+            "psql -c 'DELETE FROM users WHERE id = 3;'",
+        ];
+        for command in commands {
+            let findings = analyze(command);
+            assert!(findings.is_empty(), "{} flagged: {:?}", command, findings);
+        }
+    }
+
+    #[test]
+    fn test_most_severe_first() {
+        let findings = analyze("rm -rf / && git push -f");
+        assert_eq!(findings[0].severity, Severity::Critical);
+        assert_eq!(findings.last().unwrap().severity, Severity::Warning);
+        assert_eq!("block".parse::<DangerPolicy>(), Ok(DangerPolicy::Block));
+        assert!(DangerPolicy::Block.blocks(&findings[0]));
+        assert!(!DangerPolicy::Block.blocks(findings.last().unwrap()));
+        assert!(!DangerPolicy::Confirm.blocks(&findings[0]));
+    }
+}
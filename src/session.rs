@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::llm::ChatMessage;
+
+// env
+const ENV_XDG_DATA_HOME: &str = "XDG_DATA_HOME";
+const ENV_TMUX_PANE: &str = "TMUX_PANE";
+// exported by the shell function generated by --init
+const ENV_SHELL_PID: &str = "ASK_SH_SHELL_PID";
+
+/// Only the most recent exchanges are replayed to keep requests small
+const MAX_HISTORY_EXCHANGES: usize = 10;
+
+/// A single question and answer, as stored on disk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub timestamp: u64,
+    pub provider: String,
+    pub model: String,
+    /// What the user typed, without flags
+    pub user_input: String,
+    /// The user prompt actually sent, after template rendering
+    pub prompt: String,
+    pub response: String,
+    pub commands: Vec<String>,
+}
+
+impl Exchange {
+    pub fn new(
+        provider: &str,
+        model: &str,
+        user_input: &str,
+        prompt: &str,
+        response: &str,
+        commands: &[String],
+    ) -> Self {
+        Self {
+            timestamp: now(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            user_input: user_input.to_string(),
+            prompt: prompt.to_string(),
+            response: response.to_string(),
+            commands: commands.to_vec(),
+        }
+    }
+}
+
+/// Summary of a stored session, used by --list-sessions
+#[derive(Debug)]
+pub struct SessionSummary {
+    pub name: String,
+    pub exchanges: usize,
+    pub last_used: u64,
+    pub last_input: String,
+}
+
+/// Sessions stored as one JSON-lines file per session
+#[derive(Debug)]
+pub struct SessionStore {
+    dir: PathBuf,
+}
+
+impl SessionStore {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Store under $XDG_DATA_HOME/ask-sh/sessions (or ~/.local/share/ask-sh/sessions)
+    pub fn open_default() -> Option<Self> {
+        Some(Self::new(data_dir()?.join("sessions")))
+    }
+
+    fn path(&self, name: &str) -> PathBuf {
+        self.dir.join(format!("{}.jsonl", sanitize_name(name)))
+    }
+
+    /// Load all exchanges of a session, oldest first.
+    /// A missing session is an empty one; broken lines are skipped.
+    pub fn load(&self, name: &str) -> io::Result<Vec<Exchange>> {
+        let file = match fs::File::open(self.path(name)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut exchanges = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(exchange) = serde_json::from_str::<Exchange>(&line?) {
+                exchanges.push(exchange);
+            }
+        }
+        Ok(exchanges)
+    }
+
+    pub fn append(&self, name: &str, exchange: &Exchange) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.path(name))?;
+        let line = serde_json::to_string(exchange)?;
+        writeln!(file, "{}", line)
+    }
+
+    pub fn clear(&self, name: &str) -> io::Result<()> {
+        match fs::remove_file(self.path(name)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// All sessions, most recently used first
+    pub fn list(&self) -> io::Result<Vec<SessionSummary>> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut summaries = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("jsonl") {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_string(),
+                None => continue,
+            };
+            let exchanges = self.load(&name)?;
+            if let Some(last) = exchanges.last() {
+                summaries.push(SessionSummary {
+                    name,
+                    exchanges: exchanges.len(),
+                    last_used: last.timestamp,
+                    last_input: last.user_input.clone(),
+                });
+            }
+        }
+        summaries.sort_by_key(|summary| std::cmp::Reverse(summary.last_used));
+        Ok(summaries)
+    }
+}
+
+/// $XDG_DATA_HOME/ask-sh (or ~/.local/share/ask-sh)
+pub fn data_dir() -> Option<PathBuf> {
+    let data_home = match env::var(ENV_XDG_DATA_HOME) {
+        Ok(value) if !value.is_empty() => PathBuf::from(value),
+        _ => PathBuf::from(env::var("HOME").ok()?).join(".local/share"),
+    };
+    Some(data_home.join("ask-sh"))
+}
+
+/// Default session name: one session per tmux pane, otherwise per shell process
+pub fn default_session_name() -> String {
+    if let Ok(pane) = env::var(ENV_TMUX_PANE) {
+        return format!("tmux-{}", pane.trim_start_matches('%'));
+    }
+    match env::var(ENV_SHELL_PID) {
+        Ok(pid) if !pid.is_empty() => format!("pid-{}", pid),
+        _ => format!("pid-{}", std::os::unix::process::parent_id()),
+    }
+}
+
+/// Turn earlier exchanges into conversation turns
+pub fn history_messages(exchanges: &[Exchange]) -> Vec<ChatMessage> {
+    let skip = exchanges.len().saturating_sub(MAX_HISTORY_EXCHANGES);
+    exchanges
+        .iter()
+        .skip(skip)
+        .flat_map(|exchange| {
+            [
+                ChatMessage::user(exchange.user_input.clone()),
+                ChatMessage::assistant(exchange.response.clone()),
+            ]
+        })
+        .collect()
+}
+
+/// Human readable age of a timestamp, e.g. "5m ago"
+pub fn format_age(timestamp: u64) -> String {
+    let secs = now().saturating_sub(timestamp);
+    if secs < 60 {
+        format!("{}s ago", secs)
+    } else if secs < 60 * 60 {
+        format!("{}m ago", secs / 60)
+    } else if secs < 60 * 60 * 24 {
+        format!("{}h ago", secs / (60 * 60))
+    } else {
+        format!("{}d ago", secs / (60 * 60 * 24))
+    }
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_start_matches('.')
+        .to_string()
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::Role;
+
+    fn temp_store(label: &str) -> SessionStore {
+        let dir = env::temp_dir().join(format!("ask-sh-test-{}-{}", label, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        SessionStore::new(dir)
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("tmux-3"), "tmux-3");
+        assert_eq!(sanitize_name("../etc/passwd"), "_etc_passwd");
+        assert_eq!(sanitize_name("my session"), "my_session");
+    }
+
+    #[test]
+    fn test_session_roundtrip() {
+        let store = temp_store("roundtrip");
+        assert!(store.load("work").unwrap().is_empty());
+
+        let commands = vec!["ls -la".to_string()];
+        let exchange = Exchange::new("openai", "gpt-4o", "list", "prompt", "answer", &commands);
+        store.append("work", &exchange).unwrap();
+        store.append("work", &exchange).unwrap();
+
+        let loaded = store.load("work").unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].commands, commands);
+
+        let summaries = store.list().unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].name, "work");
+        assert_eq!(summaries[0].exchanges, 2);
+
+        store.clear("work").unwrap();
+        assert!(store.load("work").unwrap().is_empty());
+        store.clear("work").unwrap();
+    }
+
+    #[test]
+    fn test_history_messages_alternate_roles() {
+        let exchanges: Vec<Exchange> = (0..MAX_HISTORY_EXCHANGES + 2)
+            .map(|i| Exchange::new("openai", "m", &format!("q{}", i), "", "a", &[]))
+            .collect();
+        let messages = history_messages(&exchanges);
+        assert_eq!(messages.len(), MAX_HISTORY_EXCHANGES * 2);
+        assert_eq!(messages[0].content, "q2");
+        assert_eq!(messages[0].role, Role::User);
+        assert_eq!(messages[1].role, Role::Assistant);
+    }
+}
@@ -0,0 +1,455 @@
+//! Token usage and estimated cost of every request, kept in a local ledger for `ask-sh usage`
+
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs::{self, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+    str::FromStr,
+};
+
+use crate::session;
+
+/// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Price {
+    pub input: f64,
+    pub output: f64,
+}
+
+/// List prices by model name prefix; the longest matching prefix wins
+const PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-3.5-turbo", 0.5, 1.5),
+    ("gpt-4", 30.0, 60.0),
+    ("gpt-4-32k", 60.0, 120.0),
+    ("gpt-4-turbo", 10.0, 30.0),
+    ("gpt-4o", 2.5, 10.0),
+    ("gpt-4o-mini", 0.15, 0.6),
+    ("gpt-4.1", 2.0, 8.0),
+    ("gpt-4.1-mini", 0.4, 1.6),
+    ("gpt-4.1-nano", 0.1, 0.4),
+    ("o1", 15.0, 60.0),
+    ("o1-mini", 1.1, 4.4),
+    ("o3", 2.0, 8.0),
+    ("o3-mini", 1.1, 4.4),
+    ("o4-mini", 1.1, 4.4),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-3-5-haiku", 0.8, 4.0),
+    ("claude-3-5-sonnet", 3.0, 15.0),
+    ("claude-3-7-sonnet", 3.0, 15.0),
+    ("claude-sonnet-4", 3.0, 15.0),
+    ("claude-3-opus", 15.0, 75.0),
+    ("claude-opus-4", 15.0, 75.0),
+    ("gemini-1.5-flash", 0.075, 0.3),
+    ("gemini-1.5-pro", 1.25, 5.0),
+    ("gemini-2.0-flash", 0.1, 0.4),
+    ("gemini-2.5-flash", 0.3, 2.5),
+    ("gemini-2.5-pro", 1.25, 10.0),
+];
+
+/// The price of `model`, if known. `overrides` is the `[prices]` table of the config file,
+/// keyed by model name prefix like the built-in table. Ollama runs locally and is free.
+pub fn price(provider: &str, model: &str, overrides: &HashMap<String, Price>) -> Option<Price> {
+    // e.g. `openai/gpt-4o` on NanoGPT or `models/gemini-1.5-pro`
+    let name = model.rsplit('/').next().unwrap_or(model);
+    let overridden = overrides
+        .iter()
+        .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(_, price)| *price);
+    if overridden.is_some() {
+        return overridden;
+    }
+    if provider == "ollama" {
+        return Some(Price {
+            input: 0.0,
+            output: 0.0,
+        });
+    }
+    PRICES
+        .iter()
+        .filter(|(prefix, _, _)| name.starts_with(prefix))
+        .max_by_key(|(prefix, _, _)| prefix.len())
+        .map(|(_, input, output)| Price {
+            input: *input,
+            output: *output,
+        })
+}
+
+/// What to do once the monthly budget is spent
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BudgetAction {
+    /// Send the request with a warning
+    #[default]
+    Warn,
+    /// Do not send the request
+    Refuse,
+}
+
+impl FromStr for BudgetAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "warn" => Ok(BudgetAction::Warn),
+            "refuse" => Ok(BudgetAction::Refuse),
+            other => Err(format!("Unknown budget action: {}", other)),
+        }
+    }
+}
+
+/// The `[budget]` table of the config file
+///
+/// ```toml
+/// [budget]
+/// monthly = 20.0
+/// action = "refuse"
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BudgetConfig {
+    /// USD per calendar month (UTC)
+    pub monthly: Option<f64>,
+    pub action: Option<BudgetAction>,
+}
+
+/// One request, as stored in the ledger
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Record {
+    pub timestamp: u64,
+    pub provider: String,
+    pub model: String,
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+    /// The provider did not report the usage, so the tokens were estimated
+    #[serde(default)]
+    pub estimated: bool,
+    /// USD, `None` when the price of the model is unknown
+    pub cost: Option<f64>,
+}
+
+impl Record {
+    pub fn new(
+        provider: &str,
+        model: &str,
+        input_tokens: u32,
+        output_tokens: u32,
+        estimated: bool,
+        price: Option<Price>,
+    ) -> Self {
+        Self {
+            timestamp: session::now(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens,
+            output_tokens,
+            estimated,
+            cost: price.map(|price| {
+                (input_tokens as f64 * price.input + output_tokens as f64 * price.output)
+                    / 1_000_000.0
+            }),
+        }
+    }
+
+    /// `YYYY-MM-DD`, in UTC
+    pub fn date(&self) -> String {
+        date(self.timestamp)
+    }
+
+    /// `YYYY-MM`, in UTC
+    pub fn month(&self) -> String {
+        self.date()[..7].to_string()
+    }
+}
+
+/// Records of every request as one JSON-lines file
+#[derive(Debug)]
+pub struct Ledger {
+    path: PathBuf,
+}
+
+impl Ledger {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Ledger at $XDG_DATA_HOME/ask-sh/usage.jsonl (or ~/.local/share/ask-sh/usage.jsonl)
+    pub fn open_default() -> Option<Self> {
+        Some(Self::new(session::data_dir()?.join("usage.jsonl")))
+    }
+
+    /// All records, oldest first. A missing ledger is an empty one; broken lines are skipped.
+    pub fn load(&self) -> io::Result<Vec<Record>> {
+        let file = match fs::File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut records = Vec::new();
+        for line in BufReader::new(file).lines() {
+            if let Ok(record) = serde_json::from_str::<Record>(&line?) {
+                records.push(record);
+            }
+        }
+        Ok(records)
+    }
+
+    pub fn append(&self, record: &Record) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", serde_json::to_string(record)?)
+    }
+}
+
+/// `YYYY-MM-DD` of a Unix timestamp, in UTC
+pub fn date(timestamp: u64) -> String {
+    // days to civil date, from Howard Hinnant's date algorithms
+    let days = (timestamp / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Whether `text` looks like `YYYY-MM`
+pub fn is_month(text: &str) -> bool {
+    text.len() == 7
+        && text.char_indices().all(|(i, c)| match i {
+            4 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+/// USD spent in `month`, counting only the priced requests
+pub fn month_cost(records: &[Record], month: &str) -> f64 {
+    records
+        .iter()
+        .filter(|record| record.month() == month)
+        .filter_map(|record| record.cost)
+        .sum()
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Totals {
+    requests: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost: f64,
+    /// Requests to models without a price
+    unpriced: usize,
+}
+
+impl Totals {
+    fn add(&mut self, record: &Record) {
+        self.requests += 1;
+        self.input_tokens += u64::from(record.input_tokens);
+        self.output_tokens += u64::from(record.output_tokens);
+        match record.cost {
+            Some(cost) => self.cost += cost,
+            None => self.unpriced += 1,
+        }
+    }
+
+    fn row(&self, label: &str) -> String {
+        let mut cost = format!("${:.4}", self.cost);
+        if self.unpriced > 0 {
+            cost.push('*');
+        }
+        format!(
+            "  {:<40} {:>8} {:>12} {:>12} {:>12}\n",
+            label, self.requests, self.input_tokens, self.output_tokens, cost
+        )
+    }
+}
+
+fn group<'a>(
+    records: &[&'a Record],
+    key: impl Fn(&'a Record) -> String,
+) -> BTreeMap<String, Totals> {
+    let mut groups: BTreeMap<String, Totals> = BTreeMap::new();
+    for record in records {
+        groups.entry(key(record)).or_default().add(record);
+    }
+    groups
+}
+
+/// Usage in `month` by day, model and provider, with the spent share of `budget`
+pub fn report(records: &[Record], month: &str, budget: Option<f64>) -> String {
+    let records: Vec<&Record> = records
+        .iter()
+        .filter(|record| record.month() == month)
+        .collect();
+    let mut text = format!("Usage in {} (UTC)\n", month);
+    if records.is_empty() {
+        text.push_str("\nNo requests.\n");
+        return text;
+    }
+
+    let header = format!(
+        "  {:<40} {:>8} {:>12} {:>12} {:>12}\n",
+        "", "requests", "input", "output", "cost"
+    );
+    let sections: [(&str, BTreeMap<String, Totals>); 3] = [
+        ("By day", group(&records, Record::date)),
+        (
+            "By model",
+            group(&records, |record| {
+                format!("{}/{}", record.provider, record.model)
+            }),
+        ),
+        (
+            "By provider",
+            group(&records, |record| record.provider.clone()),
+        ),
+    ];
+    for (title, groups) in sections {
+        let _ = write!(text, "\n{}\n{}", title, header);
+        for (label, totals) in &groups {
+            text.push_str(&totals.row(label));
+        }
+    }
+
+    let mut total = Totals::default();
+    for record in &records {
+        total.add(record);
+    }
+    text.push('\n');
+    text.push_str(&total.row("Total"));
+    if let Some(budget) = budget {
+        let _ = writeln!(
+            text,
+            "\nBudget: ${:.2} of ${:.2} spent ({:.0}%)",
+            total.cost,
+            budget,
+            total.cost / budget * 100.0
+        );
+    }
+    if total.unpriced > 0 {
+        text.push_str(
+            "\n* Some models have no known price and count as free. Add them to [prices] in the config file.\n",
+        );
+    }
+    if records.iter().any(|record| record.estimated) {
+        text.push_str("\nTokens of providers that do not report usage are estimated.\n");
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(timestamp: u64, provider: &str, model: &str, cost: Option<f64>) -> Record {
+        Record {
+            timestamp,
+            provider: provider.to_string(),
+            model: model.to_string(),
+            input_tokens: 1000,
+            output_tokens: 100,
+            estimated: false,
+            cost,
+        }
+    }
+
+    #[test]
+    fn test_prices() {
+        let none = HashMap::new();
+        assert_eq!(
+            price("openai", "gpt-4o-mini-2024-07-18", &none),
+            Some(Price {
+                input: 0.15,
+                output: 0.6
+            })
+        );
+        assert_eq!(
+            price("nanogpt", "anthropic/claude-3-5-sonnet-20241022", &none).map(|p| p.output),
+            Some(15.0)
+        );
+        assert_eq!(
+            price("ollama", "llama3.2", &none).map(|p| p.input),
+            Some(0.0)
+        );
+        assert_eq!(price("azure", "team-deployment", &none), None);
+
+        let overrides = HashMap::from([(
+            "team-".to_string(),
+            Price {
+                input: 2.5,
+                output: 10.0,
+            },
+        )]);
+        let price = price("azure", "team-deployment", &overrides).unwrap();
+        let record = Record::new("azure", "team-deployment", 2000, 1000, false, Some(price));
+        assert!((record.cost.unwrap() - 0.015).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951_782_400), "2000-02-29");
+        assert_eq!(date(1_767_225_599), "2025-12-31");
+        assert_eq!(date(1_767_225_600), "2026-01-01");
+        assert!(is_month("2026-01"));
+        assert!(!is_month("2026-1"));
+        assert!(!is_month("df -h"));
+    }
+
+    #[test]
+    fn test_report_and_budget() {
+        // 2026-01-01 and 2026-01-02, then February
+        let records = vec![
+            record(1_767_225_600, "openai", "gpt-4o", Some(0.5)),
+            record(1_767_312_000, "openai", "gpt-4o", Some(0.25)),
+            record(1_767_312_000, "azure", "team", None),
+            record(1_769_904_000, "openai", "gpt-4o", Some(9.0)),
+        ];
+        assert!((month_cost(&records, "2026-01") - 0.75).abs() < 1e-9);
+
+        let text = report(&records, "2026-01", Some(10.0));
+        assert!(text.starts_with("Usage in 2026-01 (UTC)\n"));
+        assert!(text.contains("2026-01-01"));
+        assert!(text.contains("openai/gpt-4o"));
+        assert!(text.contains("azure/team"));
+        assert!(!text.contains("2026-02"));
+        assert!(text.contains("Budget: $0.75 of $10.00 spent (8%)"));
+        assert!(text.contains("Add them to [prices]"));
+        assert!(report(&records, "2025-12", None).contains("No requests."));
+    }
+
+    #[test]
+    fn test_ledger_round_trip() {
+        let path = std::env::temp_dir()
+            .join(format!("ask-sh-usage-{}", std::process::id()))
+            .join("usage.jsonl");
+        let ledger = Ledger::new(path.clone());
+        assert!(ledger.load().unwrap().is_empty());
+        let first = record(1_767_225_600, "anthropic", "claude-3-5-haiku", Some(0.1));
+        ledger.append(&first).unwrap();
+        ledger
+            .append(&record(1_767_225_601, "ollama", "llama3.2", Some(0.0)))
+            .unwrap();
+        let records = ledger.load().unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], first);
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+}
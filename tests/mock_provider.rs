@@ -0,0 +1,368 @@
+//! End-to-end runs of the binary against the mock provider
+
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+fn temp_dir(label: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("ask-sh-e2e-{}-{}", label, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Run `ask-sh` with `args`, replaying `fixture`, isolated from the user's config and sessions
+fn run(dir: &PathBuf, fixture: &PathBuf, args: &[&str], extra_env: &[(&str, &str)]) -> Output {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_ask-sh"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env_remove("TMUX")
+        .env("ASK_SH_LLM_PROVIDER", "mock")
+        .env("ASK_SH_MOCK_FIXTURE", fixture)
+        .env("ASK_SH_CONFIG", dir.join("config.toml"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("ASK_SH_NO_PANE", "true");
+    for (key, value) in extra_env {
+        command.env(key, value);
+    }
+    command.output().unwrap()
+}
+
+#[test]
+fn test_suggested_commands_are_printed() {
+    let dir = temp_dir("suggest");
+    let fixture = dir.join("answer.txt");
+    fs::write(
+        &fixture,
+        "List them with:\n```\nls -la\n```\nor ```ls -la``` again",
+    )
+    .unwrap();
+
+    let output = run(&dir, &fixture, &["list", "files"], &[]);
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "ls -la\t\tList them with:\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("List them with:"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_provider_errors_and_truncation_are_reported() {
+    let dir = temp_dir("errors");
+    let fixture = dir.join("answer.jsonl");
+    fs::write(
+        &fixture,
+        concat!(
+            "{\"text\":\"```pwd```\"}\n",
+            "{\"error\":{\"kind\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+            "{\"stop\":\"max_tokens\"}\n"
+        ),
+    )
+    .unwrap();
+
+    let output = run(&dir, &fixture, &["where", "am", "I"], &[]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "pwd\t\t```pwd```\n"
+    );
+    assert!(stderr.contains("mock is overloaded right now"));
+    assert!(stderr.contains("output token limit"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_record_mode_writes_a_replayable_fixture() {
+    let dir = temp_dir("record");
+    let fixture = dir.join("answer.txt");
+    fs::write(&fixture, "```whoami```").unwrap();
+    let recording = dir.join("recorded.jsonl");
+
+    let recorded = run(
+        &dir,
+        &fixture,
+        &["who", "am", "I"],
+        &[("ASK_SH_RECORD", recording.to_str().unwrap())],
+    );
+    let replayed = run(&dir, &recording, &["who", "am", "I"], &[]);
+    assert_eq!(recorded.stdout, replayed.stdout);
+    assert_eq!(
+        String::from_utf8_lossy(&replayed.stdout),
+        "whoami\t\t```whoami```\n"
+    );
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_json_formats() {
+    let dir = temp_dir("json");
+    let fixture = dir.join("answer.jsonl");
+    fs::write(
+        &fixture,
+        concat!(
+            "{\"text\":\"Show the disk usage:\\n```bash\\ndu -sh .\\n```\"}\n",
+            "{\"usage\":{\"input_tokens\":20,\"output_tokens\":9}}\n",
+            "{\"stop\":\"end_turn\"}\n"
+        ),
+    )
+    .unwrap();
+
+    let output = run(&dir, &fixture, &["--format", "json", "disk", "usage"], &[]);
+    assert!(output.status.success());
+    let answer: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(answer["provider"], "mock");
+    assert_eq!(answer["stop_reason"], "end_turn");
+    assert_eq!(answer["usage"]["output_tokens"], 9);
+    assert_eq!(answer["commands"][0]["command"], "du -sh .");
+    assert_eq!(answer["commands"][0]["language"], "bash");
+    assert_eq!(answer["commands"][0]["explanation"], "Show the disk usage:");
+    assert!(output.stderr.is_empty());
+
+    let output = run(
+        &dir,
+        &fixture,
+        &["--format", "ndjson", "disk", "usage"],
+        &[],
+    );
+    let lines: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let types: Vec<&str> = lines
+        .iter()
+        .map(|line| line["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(types, vec!["text", "usage", "stop", "result"]);
+    assert_eq!(lines[3]["commands"][0]["one_line"], "du -sh .");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_dangerous_commands_are_flagged_or_blocked() {
+    let dir = temp_dir("danger");
+    let fixture = dir.join("answer.txt");
+    fs::write(&fixture, "Clean up:\n```\nrm -rf build\n```\n```\nls\n```").unwrap();
+
+    let output = run(&dir, &fixture, &["clean", "up"], &[]);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "rm -rf build\t⚠ warning: recursively deletes files\tClean up:\nls\t\tClean up:\n"
+    );
+
+    // only critical commands are blocked, and they are not kept in the session
+    fs::write(
+        &fixture,
+        "Clean up:\n```\nrm -rf build\n```\n```\nrm -rf ~\n```\n```\nls\n```",
+    )
+    .unwrap();
+    let output = run(
+        &dir,
+        &fixture,
+        &["--session", "danger", "clean", "up"],
+        &[("ASK_SH_DANGER_POLICY", "block")],
+    );
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "rm -rf build\t⚠ warning: recursively deletes files\tClean up:\nls\t\tClean up:\n"
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`rm -rf ~` is not offered"));
+    let sessions = dir.join("data").join("ask-sh").join("sessions");
+    let saved: String = fs::read_dir(sessions)
+        .unwrap()
+        .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect();
+    assert!(saved.contains("rm -rf build"));
+    assert!(!saved.contains("rm -rf ~"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_show_redactions() {
+    let dir = temp_dir("redact");
+    let fixture = dir.join("answer.txt");
+    fs::write(&fixture, "Do not share keys.").unwrap();
+
+    let output = run(
+        &dir,
+        &fixture,
+        &[
+            "--show-redactions",
+            "why",
+            "does",
+            "sk-proj-abcdefghijklmnopqrstuvwx",
+            "fail",
+        ],
+        &[],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("1 secret(s) redacted"));
+    assert!(stderr.contains("request line 1: openai-key sk-p… (32 chars)"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_usage_ledger_and_budget() {
+    let dir = temp_dir("usage");
+    let fixture = dir.join("answer.jsonl");
+    fs::write(
+        &fixture,
+        concat!(
+            "{\"text\":\"```df -h```\"}\n",
+            "{\"usage\":{\"input_tokens\":600000,\"output_tokens\":100000}}\n",
+            "{\"stop\":\"end_turn\"}\n"
+        ),
+    )
+    .unwrap();
+    fs::write(
+        dir.join("config.toml"),
+        "[prices]\nmock = { input = 1.0, output = 4.0 }\n\n[budget]\nmonthly = 1.0\naction = \"refuse\"\n",
+    )
+    .unwrap();
+
+    let output = run(&dir, &fixture, &["disk", "space"], &[]);
+    assert!(output.status.success());
+
+    let output = run(&dir, &fixture, &["usage"], &[]);
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("mock/mock"));
+    assert!(report.contains("$1.0000"));
+    assert!(report.contains("Budget: $1.00 of $1.00 spent (100%)"));
+
+    // the budget is spent
+    let output = run(&dir, &fixture, &["disk", "space"], &[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("monthly budget of $1.00 is spent"));
+    let output = run(
+        &dir,
+        &fixture,
+        &["disk", "space"],
+        &[("ASK_SH_BUDGET_ACTION", "warn")],
+    );
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("monthly budget is spent"));
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_fallback_to_the_next_profile() {
+    let dir = temp_dir("fallback");
+    let down = dir.join("down.jsonl");
+    fs::write(
+        &down,
+        "{\"error\":{\"kind\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+    )
+    .unwrap();
+    let backup = dir.join("backup.txt");
+    fs::write(&backup, "```uptime```").unwrap();
+    fs::write(
+        dir.join("config.toml"),
+        format!(
+            concat!(
+                "default_profile = \"main\"\n\n",
+                "[profiles.main]\nprovider = \"mock\"\nbase_url = \"{}\"\n",
+                "max_retries = 0\nfallback = [\"missing\", \"backup\"]\n\n",
+                "[profiles.backup]\nprovider = \"mock\"\nbase_url = \"{}\"\nmodel = \"backup-model\"\n\n",
+                "[context_windows]\n\"backup-model\" = 100\n"
+            ),
+            down.display(),
+            backup.display()
+        ),
+    )
+    .unwrap();
+
+    // the fixtures come from the profiles, so ASK_SH_MOCK_FIXTURE is not set
+    let output = Command::new(env!("CARGO_BIN_EXE_ask-sh"))
+        .args([
+            "--format",
+            "json",
+            "--debug_ask_sh",
+            "how",
+            "long",
+            "is",
+            "it",
+            "up",
+        ])
+        .current_dir(&dir)
+        .env_remove("TMUX")
+        .env_remove("ASK_SH_LLM_PROVIDER")
+        .env_remove("ASK_SH_MOCK_FIXTURE")
+        .env("ASK_SH_CONFIG", dir.join("config.toml"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("ASK_SH_NO_PANE", "true")
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    assert!(stderr.contains("Fallback profile missing is skipped"));
+    assert!(
+        stderr.contains("Falling back to mock (backup-model) after: overloaded_error: Overloaded")
+    );
+    // the request is shortened again for the smaller window of the fallback
+    assert!(stderr.contains("context_window of mock (backup-model): 100"));
+    assert!(stderr.contains("truncated user_input"));
+    let answer: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(answer["model"], "backup-model");
+    assert_eq!(answer["commands"][0]["command"], "uptime");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_explicit_profile_keeps_its_provider() {
+    let dir = temp_dir("profile");
+    let fixture = dir.join("answer.txt");
+    fs::write(&fixture, "```uptime```").unwrap();
+    fs::write(
+        dir.join("config.toml"),
+        "[profiles.strong]\nprovider = \"mock\"\nmodel = \"strong-model\"\n",
+    )
+    .unwrap();
+
+    // an exported ASK_SH_LLM_PROVIDER does not replace the provider of --profile
+    let output = run(
+        &dir,
+        &fixture,
+        &["--profile", "strong", "--format", "json", "uptime"],
+        &[("ASK_SH_LLM_PROVIDER", "openai")],
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{}", stderr);
+    let answer: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(answer["model"], "strong-model");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_json_formats_report_failed_requests() {
+    let dir = temp_dir("json-failure");
+    let fixture = dir.join("down.jsonl");
+    fs::write(
+        &fixture,
+        "{\"error\":{\"kind\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n",
+    )
+    .unwrap();
+    let no_retries = [("ASK_SH_MAX_RETRIES", "0")];
+
+    let output = run(&dir, &fixture, &["--format", "json", "uptime"], &no_retries);
+    assert!(!output.status.success());
+    let failure: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(failure["error"]["kind"], "request");
+    assert_eq!(failure["error"]["message"], "overloaded_error: Overloaded");
+
+    let output = run(
+        &dir,
+        &fixture,
+        &["--format", "ndjson", "uptime"],
+        &no_retries,
+    );
+    assert!(!output.status.success());
+    let line: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(line["type"], "error");
+    assert_eq!(line["message"], "overloaded_error: Overloaded");
+    fs::remove_dir_all(dir).unwrap();
+}